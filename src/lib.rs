@@ -3,6 +3,17 @@ pub mod server;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
+use crate::server::clients::SessionConfig;
+use crate::server::fetch::FetchConfig;
+use crate::server::image::ImageConfig;
+use crate::server::proxypool::ProxyPoolConfig;
+use crate::server::ratelimit::ClientRateLimitConfig;
+use crate::server::search::cache::SearchCacheConfig;
+use crate::server::search::registry::{ProviderConfig, default_providers};
+use crate::server::simplifier::SimplifierConfig;
+use crate::server::simplifier::cache::PageCacheConfig;
+use crate::server::urlguard::UrlGuardConfig;
+
 pub const USER_AGENT: &str =
     "Mozilla/5.0 (compatible; IBrowse 3.0; AmigaOS4.0) BoingSearch/Preview Mode";
 
@@ -10,10 +21,55 @@ pub const USER_AGENT: &str =
 pub struct AppConfig {
     pub host: String,
     pub port: u16,
+    /// SerpAPI key, used by `serpapi` providers without own key
+    #[serde(default)]
     pub api_key: String,
     pub base_path: Url,
     pub rate_limit: i64,
     pub proxies: Vec<String>,
+    /// How long proxy isn't used after DuckDuckGo bot challenge
+    #[serde(default = "default_proxy_cooldown_secs")]
+    pub proxy_cooldown_secs: i64,
+    #[serde(default)]
+    pub proxy_pool: ProxyPoolConfig,
+    #[serde(default = "default_providers")]
+    pub providers: Vec<ProviderConfig>,
+    #[serde(default)]
+    pub search_cache: SearchCacheConfig,
+    #[serde(default)]
+    pub client_rate_limit: ClientRateLimitConfig,
+    /// Separate bucket for image converter, a single page can have dozens of images
+    #[serde(default = "default_image_rate_limit")]
+    pub image_rate_limit: ClientRateLimitConfig,
+    #[serde(default)]
+    pub simplifier: SimplifierConfig,
+    #[serde(default)]
+    pub page_cache: PageCacheConfig,
+    /// Token for admin endpoints, they are disabled if it isn't set
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    /// Reverse proxies whose `X-Forwarded-For` is believed, addresses or CIDR ranges
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    #[serde(default)]
+    pub url_guard: UrlGuardConfig,
+    #[serde(default)]
+    pub fetch: FetchConfig,
+    #[serde(default)]
+    pub sessions: SessionConfig,
+    #[serde(default)]
+    pub images: ImageConfig,
+}
+
+fn default_proxy_cooldown_secs() -> i64 {
+    900
+}
+
+fn default_image_rate_limit() -> ClientRateLimitConfig {
+    ClientRateLimitConfig {
+        requests_per_minute: 120,
+        burst: 60,
+    }
 }
 
 impl AppConfig {
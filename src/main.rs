@@ -2,12 +2,12 @@ use boing_search::{
     AppConfig,
     server::{
         Server,
-        search::{
-            SearchEngine, duckduckprovider::DuckDuckRequester, serpapiprovider::SerpApiProvider,
-        },
+        proxypool::ProxyPool,
+        search::{SearchEngine, cache::SearchCache, registry::ProviderRegistry},
     },
 };
 use log::{debug, info};
+use std::sync::Arc;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -19,12 +19,19 @@ async fn main() -> anyhow::Result<()> {
 
     debug!("Starting with config: {app_config:?}");
 
-    let free = DuckDuckRequester::new(app_config.rate_limit, app_config.proxies.clone());
-    let premium = SerpApiProvider::new(app_config.api_key.clone());
+    let proxy_pool = Arc::new(ProxyPool::new(
+        app_config.proxies.clone(),
+        app_config.proxy_pool.clone(),
+    )?);
+    proxy_pool.spawn_health_checks();
 
-    let search_engine = SearchEngine::new(free, premium);
+    let registry = ProviderRegistry::from_config(&app_config, proxy_pool.clone())?;
+    let cache = SearchCache::new(app_config.search_cache.clone());
+    let search_engine = SearchEngine::new(registry, cache);
 
-    Server::new(app_config, search_engine).start().await?;
+    Server::new(app_config, search_engine, proxy_pool)?
+        .start()
+        .await?;
 
     Ok(())
 }
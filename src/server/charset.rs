@@ -0,0 +1,282 @@
+use encoding_rs::{EncoderResult, Encoding, MACINTOSH, UTF_8, WINDOWS_1252};
+
+/// How many bytes are scanned for `<meta charset>`, same as browsers do
+const META_PRESCAN_LIMIT: usize = 1024;
+
+/// Finds encoding of fetched page: BOM first, then HTTP header, then `<meta>` tag
+pub fn detect_encoding(body: &[u8], content_type: Option<&str>) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(body) {
+        return encoding;
+    }
+
+    content_type
+        .and_then(charset_param)
+        .or_else(|| meta_charset(body))
+        .and_then(|label| Encoding::for_label(label.trim().as_bytes()))
+        .unwrap_or_else(|| {
+            // Old pages without any declaration are mostly Latin-1
+            if std::str::from_utf8(body).is_ok() {
+                UTF_8
+            } else {
+                WINDOWS_1252
+            }
+        })
+}
+
+pub fn decode_body(body: &[u8], content_type: Option<&str>) -> String {
+    let (text, _, _) = detect_encoding(body, content_type).decode(body);
+
+    text.into_owned()
+}
+
+/// Value of `charset=` parameter in Content-Type like header
+fn charset_param(header: &str) -> Option<&str> {
+    header
+        .split(';')
+        .filter_map(|p| p.trim().split_once('='))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case("charset"))
+        .map(|(_, v)| v.trim().trim_matches(|c| c == '"' || c == '\''))
+}
+
+/// Looks for both `<meta charset="...">` and `<meta http-equiv content="...; charset=...">`
+fn meta_charset(body: &[u8]) -> Option<&str> {
+    let head = &body[..body.len().min(META_PRESCAN_LIMIT)];
+    let head = match std::str::from_utf8(head) {
+        Ok(s) => s,
+        Err(e) => std::str::from_utf8(&head[..e.valid_up_to()]).ok()?,
+    };
+    let lower = head.to_ascii_lowercase();
+
+    lower.match_indices("<meta").find_map(|(start, _)| {
+        let end = lower[start..].find('>').map_or(lower.len(), |e| start + e);
+        let tag = &lower[start..end];
+        let pos = tag.find("charset=")? + "charset=".len();
+        let quotes = tag[pos..].len() - tag[pos..].trim_start_matches(['"', '\'']).len();
+        let value = &tag[pos + quotes..];
+        let len = value
+            .find(|c: char| c == '"' || c == '\'' || c == ';' || c == '/' || c.is_whitespace())
+            .unwrap_or(value.len());
+
+        // Offsets are same in lowercased copy, because only ASCII is changed
+        let value_start = start + pos + quotes;
+        Some(&head[value_start..value_start + len])
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputCharset {
+    /// Everything is transliterated to plain ASCII
+    Ascii,
+    Latin1,
+    /// Cyrillic charset of Amiga. Letters are placed as in Windows-1251, but 0x80-0x9F are
+    /// control codes there and other symbols of 0xA0-0xBF differ, so only letters and `Ё`
+    /// are sent as is
+    Amiga1251,
+    MacRoman,
+    Utf8,
+}
+
+impl OutputCharset {
+    pub const ALL: [OutputCharset; 5] = [
+        OutputCharset::Ascii,
+        OutputCharset::Latin1,
+        OutputCharset::Amiga1251,
+        OutputCharset::MacRoman,
+        OutputCharset::Utf8,
+    ];
+
+    /// Name that is sent in Content-Type header
+    pub fn label(&self) -> &'static str {
+        match self {
+            OutputCharset::Ascii => "us-ascii",
+            OutputCharset::Latin1 => "iso-8859-1",
+            OutputCharset::Amiga1251 => "Amiga-1251",
+            OutputCharset::MacRoman => "macintosh",
+            OutputCharset::Utf8 => "utf-8",
+        }
+    }
+
+    pub fn from_label(label: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|c| c.label().eq_ignore_ascii_case(label))
+    }
+
+    pub fn content_type(&self) -> String {
+        format!("text/html; charset={}", self.label())
+    }
+
+    /// Transcodes text, characters that charset hasn't got are transliterated
+    pub fn encode(&self, html: &str) -> Vec<u8> {
+        match self {
+            OutputCharset::Utf8 => html.as_bytes().to_vec(),
+            OutputCharset::Ascii => html
+                .chars()
+                .flat_map(|c| match c.is_ascii() {
+                    true => vec![c as u8],
+                    false => transliterate(c),
+                })
+                .collect(),
+            OutputCharset::Latin1 => encode_latin1(html),
+            OutputCharset::Amiga1251 => encode_amiga1251(html),
+            OutputCharset::MacRoman => encode_with(MACINTOSH, html),
+        }
+    }
+
+    /// Text that browser sent back in this charset
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        match self {
+            OutputCharset::Ascii | OutputCharset::Utf8 => String::from_utf8_lossy(bytes).into(),
+            OutputCharset::Latin1 => bytes.iter().map(|b| *b as char).collect(),
+            OutputCharset::Amiga1251 => decode_amiga1251(bytes),
+            OutputCharset::MacRoman => MACINTOSH.decode_without_bom_handling(bytes).0.into(),
+        }
+    }
+}
+
+/// Real ISO-8859-1, encoding_rs treats this label as Windows-1252
+fn encode_latin1(text: &str) -> Vec<u8> {
+    let mut result = Vec::with_capacity(text.len());
+
+    for c in text.chars() {
+        match u8::try_from(c as u32) {
+            Ok(b) if !(0x80..0xa0).contains(&b) => result.push(b),
+            _ => result.extend(transliterate(c)),
+        }
+    }
+
+    result
+}
+
+/// First Cyrillic letter `А` is 0xC0, alphabet goes without gaps till `я` at 0xFF
+const AMIGA_1251_LETTERS: u32 = 0x410;
+
+fn encode_amiga1251(text: &str) -> Vec<u8> {
+    let mut result = Vec::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            _ if c.is_ascii() => result.push(c as u8),
+            'А'..='я' => result.push((c as u32 - AMIGA_1251_LETTERS + 0xc0) as u8),
+            'Ё' => result.push(0xa8),
+            'ё' => result.push(0xb8),
+            _ => result.extend(transliterate(c)),
+        }
+    }
+
+    result
+}
+
+/// Bytes between ASCII and letters, except `Ё` and `ё`, aren't the same as in Windows-1251, they
+/// are replaced
+fn decode_amiga1251(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| match b {
+            0..0x80 => *b as char,
+            0xa8 => 'Ё',
+            0xb8 => 'ё',
+            0xc0.. => char::from_u32(*b as u32 - 0xc0 + AMIGA_1251_LETTERS)
+                .unwrap_or(char::REPLACEMENT_CHARACTER),
+            _ => char::REPLACEMENT_CHARACTER,
+        })
+        .collect()
+}
+
+fn encode_with(encoding: &'static Encoding, text: &str) -> Vec<u8> {
+    let mut encoder = encoding.new_encoder();
+    let mut result = Vec::with_capacity(text.len() + 16);
+    let mut rest = text;
+
+    loop {
+        let max_len = encoder
+            .max_buffer_length_from_utf8_without_replacement(rest.len())
+            .unwrap_or(rest.len() * 4);
+        result.reserve(max_len);
+
+        let (status, read) =
+            encoder.encode_from_utf8_to_vec_without_replacement(rest, &mut result, true);
+        rest = &rest[read..];
+
+        match status {
+            EncoderResult::InputEmpty => return result,
+            EncoderResult::Unmappable(c) => result.extend(transliterate(c)),
+            EncoderResult::OutputFull => {}
+        }
+    }
+}
+
+/// Text is already serialized HTML, so markup characters that transliteration makes, like
+/// `<<` for `«`, are escaped
+fn transliterate(c: char) -> Vec<u8> {
+    let mut result = vec![];
+
+    for b in deunicode::deunicode_char(c).unwrap_or("?").bytes() {
+        match b {
+            b'<' => result.extend(b"&lt;"),
+            b'>' => result.extend(b"&gt;"),
+            b'&' => result.extend(b"&amp;"),
+            _ if b.is_ascii() => result.push(b),
+            _ => {}
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::WINDOWS_1251;
+
+    #[test]
+    fn test_detect_encoding() {
+        let bom = b"\xef\xbb\xbf<html>";
+        assert_eq!(
+            detect_encoding(bom, Some("text/html; charset=koi8-r")),
+            UTF_8
+        );
+
+        let page = "<html><head><meta charset=\"koi8-r\"></head>".as_bytes();
+        assert_eq!(
+            detect_encoding(page, Some("text/html; charset=\"windows-1251\"")),
+            WINDOWS_1251
+        );
+        assert_eq!(detect_encoding(page, Some("text/html")).name(), "KOI8-R");
+
+        let equiv = b"<META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=ISO-8859-2\">";
+        assert_eq!(detect_encoding(equiv, None).name(), "ISO-8859-2");
+
+        assert_eq!(decode_body(b"Caf\xe9", None), "Café");
+    }
+
+    #[test]
+    fn test_encode_output() {
+        let text = "Café – Привет €";
+
+        assert_eq!(OutputCharset::Latin1.encode(text), b"Caf\xe9 - Privet EUR");
+        assert_eq!(
+            OutputCharset::Amiga1251.encode(text),
+            b"Cafe - \xcf\xf0\xe8\xe2\xe5\xf2 EUR"
+        );
+        assert_eq!(
+            OutputCharset::Amiga1251.decode(b"\xcf\xf0\xe8\xe2\xe5\xf2 \x96"),
+            "Привет \u{fffd}"
+        );
+        assert_eq!(OutputCharset::MacRoman.encode("é"), b"\x8e");
+        assert_eq!(
+            OutputCharset::Ascii.encode("<q>«Amiga»</q>"),
+            b"<q>&lt;&lt;Amiga&gt;&gt;</q>"
+        );
+        assert_eq!(
+            OutputCharset::Amiga1251.encode("Ёлка ё"),
+            b"\xa8\xeb\xea\xe0 \xb8"
+        );
+        assert_eq!(OutputCharset::Amiga1251.decode(b"\xa8\xb8"), "Ёё");
+        assert_eq!(OutputCharset::Ascii.encode(text), b"Cafe - Privet EUR");
+        assert_eq!(
+            OutputCharset::from_label("AMIGA-1251"),
+            Some(OutputCharset::Amiga1251)
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::http::HeaderMap;
+use log::debug;
+use rand::Rng;
+use reqwest::cookie::{CookieStore, Jar};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::server::fetch::{self, FetchConfig};
+use crate::server::profile::cookie_value;
+use crate::server::urlguard::UrlGuard;
+
+/// Cookie that ties visitor to own cookie jar
+pub const SESSION_COOKIE: &str = "boing_session";
+
+/// `[sessions]` section of config.toml
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionConfig {
+    /// Keep cookies of visited sites for every visitor
+    #[serde(default)]
+    pub enabled: bool,
+    /// Cookie jar is dropped when it isn't used for this time
+    #[serde(default = "default_idle_secs")]
+    pub idle_secs: u64,
+    #[serde(default = "default_max_sessions")]
+    pub max_sessions: usize,
+}
+
+fn default_idle_secs() -> u64 {
+    1800
+}
+
+fn default_max_sessions() -> usize {
+    1000
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            idle_secs: default_idle_secs(),
+            max_sessions: default_max_sessions(),
+        }
+    }
+}
+
+/// Client for pages and images requested by visitor
+#[derive(Clone)]
+pub struct FetchClient {
+    client: reqwest::Client,
+    /// Only set for clients of sessions
+    jar: Option<Arc<Jar>>,
+    guard: Arc<UrlGuard>,
+    pub limits: FetchConfig,
+}
+
+impl FetchClient {
+    pub async fn get(&self, url: Url) -> anyhow::Result<reqwest::Response> {
+        self.guard.check_url(&url)?;
+
+        fetch::send(self.client.get(url)).await
+    }
+
+    pub async fn post_form(
+        &self,
+        url: Url,
+        fields: &[(String, String)],
+    ) -> anyhow::Result<reqwest::Response> {
+        self.guard.check_url(&url)?;
+
+        fetch::send(self.client.post(url).form(fields)).await
+    }
+
+    /// Pages fetched with visitor's cookies can't be shared with others
+    pub fn is_private(&self, url: &Url) -> bool {
+        self.jar
+            .as_ref()
+            .is_some_and(|jar| jar.cookies(url).is_some())
+    }
+
+    pub fn has_session(&self) -> bool {
+        self.jar.is_some()
+    }
+}
+
+struct Session {
+    client: FetchClient,
+    last_used: Instant,
+}
+
+/// Keeps clients alive between requests, so connections and TLS sessions are reused
+pub struct ClientFactory {
+    shared: FetchClient,
+    guard: Arc<UrlGuard>,
+    limits: FetchConfig,
+    config: SessionConfig,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl ClientFactory {
+    pub fn new(
+        guard: Arc<UrlGuard>,
+        limits: FetchConfig,
+        config: SessionConfig,
+    ) -> anyhow::Result<Self> {
+        let shared = Self::build(&guard, &limits, None)?;
+
+        Ok(Self {
+            shared,
+            guard,
+            limits,
+            config,
+            sessions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn build(
+        guard: &Arc<UrlGuard>,
+        limits: &FetchConfig,
+        jar: Option<Arc<Jar>>,
+    ) -> anyhow::Result<FetchClient> {
+        let mut builder = limits.apply(guard.client_builder());
+        if let Some(jar) = &jar {
+            builder = builder.cookie_provider(jar.clone());
+        }
+
+        Ok(FetchClient {
+            client: builder.build()?,
+            jar,
+            guard: guard.clone(),
+            limits: limits.clone(),
+        })
+    }
+
+    pub fn sessions_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Id from visitor's cookie, made up ids aren't accepted
+    pub fn session_id(headers: &HeaderMap) -> Option<String> {
+        cookie_value(headers, SESSION_COOKIE)
+            .filter(|id| id.len() == 32 && id.chars().all(|c| c.is_ascii_hexdigit()))
+            .map(str::to_string)
+    }
+
+    pub fn new_session_id() -> String {
+        format!("{:032x}", rand::rng().random::<u128>())
+    }
+
+    pub fn shared(&self) -> FetchClient {
+        self.shared.clone()
+    }
+
+    /// Client with visitor's cookie jar, or shared one when sessions are disabled
+    pub fn for_session(&self, session: Option<&str>) -> anyhow::Result<FetchClient> {
+        let Some(session) = session.filter(|_| self.config.enabled) else {
+            return Ok(self.shared());
+        };
+
+        let Ok(mut sessions) = self.sessions.lock() else {
+            return Ok(self.shared());
+        };
+        let now = Instant::now();
+
+        if let Some(entry) = sessions.get_mut(session) {
+            entry.last_used = now;
+            return Ok(entry.client.clone());
+        }
+
+        let idle = Duration::from_secs(self.config.idle_secs);
+        sessions.retain(|_, entry| now.duration_since(entry.last_used) < idle);
+
+        while !sessions.is_empty() && sessions.len() >= self.config.max_sessions {
+            let oldest = sessions
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(id, _)| id.clone());
+
+            if let Some(oldest) = oldest {
+                sessions.remove(&oldest);
+            }
+        }
+
+        debug!("New cookie jar for session, {} active", sessions.len() + 1);
+
+        let client = Self::build(&self.guard, &self.limits, Some(Arc::new(Jar::default())))?;
+        sessions.insert(
+            session.to_string(),
+            Session {
+                client: client.clone(),
+                last_used: now,
+            },
+        );
+
+        Ok(client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::urlguard::UrlGuardConfig;
+
+    fn make_factory(enabled: bool) -> ClientFactory {
+        ClientFactory::new(
+            Arc::new(UrlGuard::new(&UrlGuardConfig::default())),
+            FetchConfig::default(),
+            SessionConfig {
+                enabled,
+                max_sessions: 2,
+                ..SessionConfig::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_session_jars() {
+        let url = Url::parse("http://aminet.net/").unwrap();
+        let factory = make_factory(true);
+
+        let first = factory.for_session(Some("first")).unwrap();
+        first
+            .jar
+            .as_ref()
+            .unwrap()
+            .add_cookie_str("consent=yes", &url);
+
+        assert!(factory.for_session(Some("first")).unwrap().is_private(&url));
+        assert!(
+            !factory
+                .for_session(Some("second"))
+                .unwrap()
+                .is_private(&url)
+        );
+        assert!(!factory.for_session(None).unwrap().has_session());
+
+        // Oldest jar goes away when there are too many of them
+        factory.for_session(Some("third")).unwrap();
+        assert!(!factory.for_session(Some("first")).unwrap().is_private(&url));
+
+        assert!(
+            !make_factory(false)
+                .for_session(Some("first"))
+                .unwrap()
+                .has_session()
+        );
+    }
+
+    #[test]
+    fn test_session_id_from_cookie() {
+        let id = ClientFactory::new_session_id();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            axum::http::header::COOKIE,
+            format!("{SESSION_COOKIE}={id}").parse().unwrap(),
+        );
+        assert_eq!(ClientFactory::session_id(&headers), Some(id));
+
+        headers.insert(
+            axum::http::header::COOKIE,
+            format!("{SESSION_COOKIE}=../../etc").parse().unwrap(),
+        );
+        assert_eq!(ClientFactory::session_id(&headers), None);
+    }
+}
@@ -0,0 +1,207 @@
+use std::fmt::Display;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// `[fetch]` section of config.toml, limits for pages and images fetched for visitors
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FetchConfig {
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+    /// Longest pause between two parts of body
+    #[serde(default = "default_read_timeout_secs")]
+    pub read_timeout_secs: u64,
+    /// Whole request including body
+    #[serde(default = "default_total_timeout_secs")]
+    pub total_timeout_secs: u64,
+    #[serde(default = "default_max_page_bytes")]
+    pub max_page_bytes: usize,
+    #[serde(default = "default_max_image_bytes")]
+    pub max_image_bytes: usize,
+}
+
+fn default_connect_timeout_secs() -> u64 {
+    10
+}
+
+fn default_read_timeout_secs() -> u64 {
+    15
+}
+
+fn default_total_timeout_secs() -> u64 {
+    60
+}
+
+fn default_max_page_bytes() -> usize {
+    2 * 1024 * 1024
+}
+
+fn default_max_image_bytes() -> usize {
+    5 * 1024 * 1024
+}
+
+impl Default for FetchConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: default_connect_timeout_secs(),
+            read_timeout_secs: default_read_timeout_secs(),
+            total_timeout_secs: default_total_timeout_secs(),
+            max_page_bytes: default_max_page_bytes(),
+            max_image_bytes: default_max_image_bytes(),
+        }
+    }
+}
+
+impl FetchConfig {
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout_secs)
+    }
+
+    pub fn apply(&self, builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        builder
+            .connect_timeout(Duration::from_secs(self.connect_timeout_secs))
+            .timeout(Duration::from_secs(self.total_timeout_secs))
+    }
+}
+
+#[derive(Debug)]
+pub enum FetchError {
+    TooLarge(usize),
+    Timeout,
+    NotHtml(String),
+}
+
+impl Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FetchError::TooLarge(limit) => write!(
+                f,
+                "This page is too big for BoingSearch, the limit is {} KB",
+                limit / 1024
+            ),
+            FetchError::Timeout => write!(f, "Site is too slow to answer, try again later"),
+            FetchError::NotHtml(content_type) => write!(
+                f,
+                "This isn't a web page, but \"{content_type}\". Open full version to download it"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+fn map_timeout(e: reqwest::Error) -> anyhow::Error {
+    if e.is_timeout() {
+        FetchError::Timeout.into()
+    } else {
+        e.into()
+    }
+}
+
+pub async fn send(request: reqwest::RequestBuilder) -> anyhow::Result<reqwest::Response> {
+    request.send().await.map_err(map_timeout)
+}
+
+/// Reads body by parts and stops as soon as it gets bigger than `max_bytes`
+pub async fn read_body(
+    mut response: reqwest::Response,
+    max_bytes: usize,
+    read_timeout: Duration,
+) -> anyhow::Result<Vec<u8>> {
+    if response
+        .content_length()
+        .is_some_and(|len| len as usize > max_bytes)
+    {
+        return Err(FetchError::TooLarge(max_bytes).into());
+    }
+
+    let mut body = Vec::new();
+
+    loop {
+        let chunk = tokio::time::timeout(read_timeout, response.chunk())
+            .await
+            .map_err(|_| FetchError::Timeout)?
+            .map_err(map_timeout)?;
+
+        let Some(chunk) = chunk else {
+            return Ok(body);
+        };
+
+        if body.len() + chunk.len() > max_bytes {
+            return Err(FetchError::TooLarge(max_bytes).into());
+        }
+
+        body.extend_from_slice(&chunk);
+    }
+}
+
+/// Missing Content-Type is treated as HTML, old servers often don't send it
+pub fn ensure_html(content_type: Option<&str>) -> Result<(), FetchError> {
+    let Some(content_type) = content_type else {
+        return Ok(());
+    };
+
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    match mime.as_str() {
+        "" | "text/html" | "application/xhtml+xml" | "text/plain" => Ok(()),
+        _ => Err(FetchError::NotHtml(mime)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::routing::get;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_body_limits() -> anyhow::Result<()> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let router = axum::Router::new()
+            .route("/big", get(|| async { "a".repeat(4096) }))
+            .route(
+                "/stalled",
+                get(|| async {
+                    let parts = futures::stream::once(async { Ok::<_, std::io::Error>("<p>") })
+                        .chain(futures::stream::pending());
+                    Body::from_stream(parts)
+                }),
+            );
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let client = reqwest::Client::new();
+        let timeout = Duration::from_millis(200);
+
+        let response = client.get(format!("http://{addr}/big")).send().await?;
+        let error = read_body(response, 1024, timeout).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(FetchError::TooLarge(1024))
+        ));
+
+        let response = client.get(format!("http://{addr}/big")).send().await?;
+        assert_eq!(read_body(response, 8192, timeout).await?.len(), 4096);
+
+        let response = client.get(format!("http://{addr}/stalled")).send().await?;
+        let error = read_body(response, 1024, timeout).await.unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(FetchError::Timeout)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_html_content_types() {
+        assert!(ensure_html(Some("text/html; charset=utf-8")).is_ok());
+        assert!(ensure_html(Some("Application/XHTML+XML")).is_ok());
+        assert!(ensure_html(None).is_ok());
+        assert!(ensure_html(Some("application/zip")).is_err());
+    }
+}
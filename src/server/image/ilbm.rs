@@ -0,0 +1,412 @@
+use image::RgbImage;
+
+use crate::server::image::PixelAspect;
+use crate::server::image::palette::{PaletteOptions, median_cut, quantize};
+
+/// Type that datatypes-capable browsers and viewers recognise
+pub const ILBM_MIME: &str = "image/x-ilbm";
+
+/// Viewport mode flags of CAMG chunk
+const CAMG_LACE: u32 = 0x0004;
+const CAMG_HAM: u32 = 0x0800;
+const CAMG_HIRES: u32 = 0x8000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IlbmMode {
+    /// Up to 256 colours from palette
+    Indexed,
+    /// Hold-and-modify with 16 base colours, shown by any Amiga
+    Ham6,
+    /// Hold-and-modify with 64 base colours, needs AGA
+    Ham8,
+}
+
+impl IlbmMode {
+    /// Bits that are changed by modify pixels, per channel
+    fn ham_bits(&self) -> u8 {
+        match self {
+            IlbmMode::Ham8 => 6,
+            _ => 4,
+        }
+    }
+}
+
+/// ILBM file, pixels are reduced to palette or encoded as HAM
+pub fn write_ilbm(
+    img: &RgbImage,
+    mode: IlbmMode,
+    palette: &PaletteOptions,
+    aspect: PixelAspect,
+) -> anyhow::Result<Vec<u8>> {
+    let width = u16::try_from(img.width())?;
+    let height = u16::try_from(img.height())?;
+
+    let (planes, palette, pixels) = match mode {
+        IlbmMode::Indexed => {
+            let indexed = quantize(img, palette);
+            let planes = (usize::BITS - (indexed.palette.len().max(2) - 1).leading_zeros()) as u8;
+
+            (planes, indexed.palette, indexed.indices)
+        }
+        IlbmMode::Ham6 | IlbmMode::Ham8 => {
+            let (palette, pixels) = encode_ham(img, mode.ham_bits());
+
+            (mode.ham_bits() + 2, palette, pixels)
+        }
+    };
+
+    let mut camg = 0;
+    if mode != IlbmMode::Indexed {
+        camg |= CAMG_HAM;
+    }
+    // Wider than lores overscan and taller than PAL one. HAM6 has no hires mode on OCS and ECS,
+    // so wide HAM6 images are left in lores for viewer to scroll
+    if width > 384 && mode != IlbmMode::Ham6 {
+        camg |= CAMG_HIRES;
+    }
+    if height > 290 {
+        camg |= CAMG_LACE;
+    }
+
+    let mut bmhd = vec![];
+    bmhd.extend(width.to_be_bytes());
+    bmhd.extend(height.to_be_bytes());
+    // Position
+    bmhd.extend([0, 0, 0, 0]);
+    // Planes, no masking, ByteRun1 compression and pad byte
+    bmhd.extend([planes, 0, 1, 0]);
+    // Transparent colour
+    bmhd.extend([0, 0]);
+    bmhd.extend([aspect.x, aspect.y]);
+    bmhd.extend(width.to_be_bytes());
+    bmhd.extend(height.to_be_bytes());
+
+    let mut form = b"ILBM".to_vec();
+    write_chunk(&mut form, b"BMHD", &bmhd);
+    write_chunk(&mut form, b"CMAP", &palette.concat());
+    write_chunk(&mut form, b"CAMG", &camg.to_be_bytes());
+    write_chunk(&mut form, b"BODY", &body(&pixels, width as usize, planes));
+
+    let mut result = vec![];
+    write_chunk(&mut result, b"FORM", &form);
+
+    Ok(result)
+}
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    out.extend(id);
+    out.extend((data.len() as u32).to_be_bytes());
+    out.extend(data);
+
+    // Chunks are aligned to words
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// Interleaved bitplanes, every row of every plane is packed separately
+fn body(pixels: &[u8], width: usize, planes: u8) -> Vec<u8> {
+    let row_bytes = width.div_ceil(16) * 2;
+    let mut result = vec![];
+
+    for row in pixels.chunks(width.max(1)) {
+        for plane in 0..planes {
+            let mut bits = vec![0u8; row_bytes];
+            for (x, pixel) in row.iter().enumerate() {
+                if pixel >> plane & 1 == 1 {
+                    bits[x / 8] |= 0x80 >> (x % 8);
+                }
+            }
+
+            byte_run1(&bits, &mut result);
+        }
+    }
+
+    result
+}
+
+/// ByteRun1 packer: `n` then `n + 1` literal bytes, or `-n` then byte repeated `n + 1` times
+fn byte_run1(data: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+
+    while i < data.len() {
+        let run = data[i..]
+            .iter()
+            .take(128)
+            .take_while(|b| **b == data[i])
+            .count();
+
+        // Repeating two bytes takes as much space as copying them
+        if run >= 3 {
+            out.push((257 - run) as u8);
+            out.push(data[i]);
+            i += run;
+            continue;
+        }
+
+        let start = i;
+        while i < data.len()
+            && i - start < 128
+            && !(i + 2 < data.len() && data[i] == data[i + 1] && data[i] == data[i + 2])
+        {
+            i += 1;
+        }
+
+        out.push((i - start - 1) as u8);
+        out.extend(&data[start..i]);
+    }
+}
+
+/// Channel that has only `bits` significant bits, scaled back to 8 bits the way chipset does
+fn expand(value: u8, bits: u8) -> u8 {
+    let value = value >> (8 - bits);
+    (value << (8 - bits)) | (value >> (2 * bits - 8))
+}
+
+/// Channel after modify pixel. HAM6 sets whole 4-bit channel, HAM8 sets upper 6 bits and keeps
+/// lower 2 bits of previous colour
+fn modify(previous: u8, value: u8, bits: u8) -> u8 {
+    match bits {
+        6 => value << 2 | previous & 3,
+        _ => value << 4 | value,
+    }
+}
+
+fn distance(a: [u8; 3], b: [u8; 3]) -> i32 {
+    (0..3).map(|c| (a[c] as i32 - b[c] as i32).pow(2)).sum()
+}
+
+/// Every pixel is either base colour or previous one with single channel changed. Choice is
+/// greedy, the one closest to source pixel wins
+fn encode_ham(img: &RgbImage, bits: u8) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let pixels = img.pixels().map(|p| p.0).collect::<Vec<_>>();
+    let palette = median_cut(&pixels, 1 << bits)
+        .into_iter()
+        .map(|color| color.map(|c| expand(c, bits)))
+        .collect::<Vec<_>>();
+
+    let width = img.width().max(1) as usize;
+    let mut result = Vec::with_capacity(pixels.len());
+
+    for row in pixels.chunks(width) {
+        // Every line starts from background colour
+        let mut current = palette[0];
+
+        for target in row {
+            let (base, base_color) = palette
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, color)| distance(**color, *target))
+                .map(|(i, color)| (i as u8, *color))
+                .unwrap_or((0, current));
+
+            let mut best = (distance(base_color, *target), base, base_color);
+
+            // Control bits: 01 modifies blue, 10 red, 11 green
+            for (control, channel) in [(1u8, 2), (2, 0), (3, 1)] {
+                let value = target[channel] >> (8 - bits);
+                let mut color = current;
+                color[channel] = modify(current[channel], value, bits);
+
+                let error = distance(color, *target);
+                if error < best.0 {
+                    best = (error, control << bits | value, color);
+                }
+            }
+
+            result.push(best.1);
+            current = best.2;
+        }
+    }
+
+    (palette, result)
+}
+
+/// Name that is offered when IFF is saved, taken from original image url
+pub fn file_name(url: &str) -> String {
+    let stem = url::Url::parse(url)
+        .ok()
+        .and_then(|url| {
+            let segment = url.path_segments()?.next_back()?.to_string();
+            let stem = segment.split('.').next()?.to_string();
+
+            Some(
+                stem.chars()
+                    .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+                    .take(26)
+                    .collect::<String>(),
+            )
+        })
+        .filter(|stem| !stem.is_empty())
+        .unwrap_or("image".to_string());
+
+    format!("{stem}.iff")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::image::palette::Dither;
+
+    fn chunks(data: &[u8]) -> Vec<(String, &[u8])> {
+        let mut result = vec![];
+        let mut i = 0;
+
+        while i + 8 <= data.len() {
+            let id = String::from_utf8_lossy(&data[i..i + 4]).to_string();
+            let len = u32::from_be_bytes(data[i + 4..i + 8].try_into().unwrap()) as usize;
+            result.push((id, &data[i + 8..i + 8 + len]));
+            i += 8 + len + len % 2;
+        }
+
+        result
+    }
+
+    fn unpack(mut data: &[u8]) -> Vec<u8> {
+        let mut result = vec![];
+
+        while let Some((&n, rest)) = data.split_first() {
+            if n < 128 {
+                result.extend(&rest[..=n as usize]);
+                data = &rest[n as usize + 1..];
+            } else {
+                result.extend(std::iter::repeat_n(rest[0], 257 - n as usize));
+                data = &rest[1..];
+            }
+        }
+
+        result
+    }
+
+    /// Pixel values back from interleaved planes
+    fn decode(data: &[u8], width: usize, height: usize, planes: u8) -> Vec<u8> {
+        let body = unpack(data);
+        let row_bytes = width.div_ceil(16) * 2;
+        let mut result = vec![0u8; width * height];
+
+        for y in 0..height {
+            for plane in 0..planes as usize {
+                let bits = &body[(y * planes as usize + plane) * row_bytes..];
+                for x in 0..width {
+                    if bits[x / 8] & (0x80 >> (x % 8)) != 0 {
+                        result[y * width + x] |= 1 << plane;
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
+    #[test]
+    fn test_byte_run1() {
+        let data = [[1, 2, 3].as_slice(), &[7; 200], &[4, 4, 5]].concat();
+        let mut packed = vec![];
+        byte_run1(&data, &mut packed);
+
+        assert_eq!(unpack(&packed), data);
+        assert!(packed.len() < 20);
+    }
+
+    #[test]
+    fn test_indexed_and_ham() {
+        let img = RgbImage::from_fn(40, 8, |x, y| image::Rgb([x as u8 * 6, y as u8 * 30, 90]));
+
+        let indexed = write_ilbm(
+            &img,
+            IlbmMode::Indexed,
+            &PaletteOptions::adaptive(16, Dither::None),
+            PixelAspect::SQUARE,
+        )
+        .unwrap();
+        let form = chunks(&indexed);
+        assert_eq!(form[0].0, "FORM");
+        assert_eq!(&form[0].1[..4], b"ILBM");
+
+        let inner = chunks(&form[0].1[4..]);
+        let names = inner.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["BMHD", "CMAP", "CAMG", "BODY"]);
+
+        let bmhd = inner[0].1;
+        assert_eq!(&bmhd[..4], &[0, 40, 0, 8]);
+        // 16 colours fit in 4 planes
+        assert_eq!(bmhd[8], 4);
+        let expected = quantize(&img, &PaletteOptions::adaptive(16, Dither::None));
+        assert_eq!(decode(inner[3].1, 40, 8, 4), expected.indices);
+
+        let ham = write_ilbm(
+            &img,
+            IlbmMode::Ham6,
+            &PaletteOptions::default(),
+            PixelAspect { x: 10, y: 11 },
+        )
+        .unwrap();
+        let inner = chunks(&chunks(&ham)[0].1[4..]);
+        assert_eq!(inner[0].1[8], 6);
+        assert_eq!(&inner[0].1[14..16], &[10, 11]);
+        assert_eq!(inner[1].1.len(), 16 * 3);
+        assert_eq!(inner[2].1, CAMG_HAM.to_be_bytes());
+
+        assert_ham_close(&img, &ham, 4, 3 * 24 * 24);
+
+        // HAM8 modify keeps lower bits of previous colour, so decoded image is much closer
+        let ham8 = write_ilbm(
+            &img,
+            IlbmMode::Ham8,
+            &PaletteOptions::default(),
+            PixelAspect::SQUARE,
+        )
+        .unwrap();
+        assert_ham_close(&img, &ham8, 6, 3 * 8 * 8);
+    }
+
+    /// Decodes HAM the way chipset does and compares it with source
+    fn assert_ham_close(img: &RgbImage, ilbm: &[u8], bits: u8, max_error: i32) {
+        let inner = chunks(&chunks(ilbm)[0].1[4..]);
+        let (width, height) = img.dimensions();
+        let palette = inner[1].1.chunks(3).collect::<Vec<_>>();
+        let pixels = decode(inner[3].1, width as usize, height as usize, bits + 2);
+        let mask = (1 << bits) - 1;
+
+        for (y, row) in pixels.chunks(width as usize).enumerate() {
+            let mut color = [palette[0][0], palette[0][1], palette[0][2]];
+            for (x, pixel) in row.iter().enumerate() {
+                match pixel >> bits {
+                    0 => color.copy_from_slice(palette[(pixel & mask) as usize]),
+                    control => {
+                        let channel = [2, 0, 1][control as usize - 1];
+                        let value = pixel & mask;
+                        color[channel] = match bits {
+                            6 => value << 2 | color[channel] & 3,
+                            _ => value * 17,
+                        };
+                    }
+                }
+
+                let source = img.get_pixel(x as u32, y as u32).0;
+                assert!(distance(color, source) < max_error);
+            }
+        }
+    }
+
+    #[test]
+    fn test_wide_ham6_stays_lores() {
+        let img = RgbImage::from_pixel(640, 4, image::Rgb([10, 20, 30]));
+        let camg = |mode| {
+            let data =
+                write_ilbm(&img, mode, &PaletteOptions::default(), PixelAspect::SQUARE).unwrap();
+            let inner = chunks(&chunks(&data)[0].1[4..]);
+            u32::from_be_bytes(inner[2].1.try_into().unwrap())
+        };
+
+        assert_eq!(camg(IlbmMode::Ham6), CAMG_HAM);
+        assert_eq!(camg(IlbmMode::Ham8), CAMG_HAM | CAMG_HIRES);
+        assert_eq!(camg(IlbmMode::Indexed), CAMG_HIRES);
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name("https://aminet.net/pix/boing.gif"), "boing.iff");
+        assert_eq!(file_name("https://aminet.net/"), "image.iff");
+    }
+}
@@ -1,65 +1,297 @@
+pub mod ilbm;
+pub mod palette;
+
+use std::fmt::Display;
 use std::io::Cursor;
 
-use image::ImageReader;
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageReader, Limits};
 use log::debug;
+use serde::{Deserialize, Serialize};
 use url::Url;
 
+use crate::server::clients::FetchClient;
+use crate::server::fetch;
+use crate::server::image::ilbm::write_ilbm;
+use crate::server::image::palette::{Dither, PaletteOptions, quantize};
+use crate::server::profile::{ImageFormat, ImageProfile};
+
+/// `[images]` section of config.toml, the biggest images visitors can ask for
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImageConfig {
+    #[serde(default = "default_max_width")]
+    pub max_width: u32,
+    #[serde(default = "default_max_height")]
+    pub max_height: u32,
+}
+
+fn default_max_width() -> u32 {
+    1024
+}
+
+fn default_max_height() -> u32 {
+    768
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            max_width: default_max_width(),
+            max_height: default_max_height(),
+        }
+    }
+}
+
+/// Width to height of single screen pixel, PAL and NTSC modes rarely have square ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelAspect {
+    pub x: u8,
+    pub y: u8,
+}
+
+impl PixelAspect {
+    pub const SQUARE: Self = Self { x: 1, y: 1 };
+
+    /// `x:y`, like `1:2` for hires non-interlaced screens
+    pub fn parse(value: &str) -> Option<Self> {
+        let (x, y) = value.split_once(':')?;
+        let x = x.trim().parse::<u8>().ok().filter(|x| *x > 0)?;
+        let y = y.trim().parse::<u8>().ok().filter(|y| *y > 0)?;
+
+        Some(Self { x, y })
+    }
+}
+
+impl Display for PixelAspect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.x, self.y)
+    }
+}
+
+/// `WIDTHxHEIGHT`, like `640x512`
+pub fn parse_size(value: &str) -> Option<(u32, u32)> {
+    let (width, height) = value
+        .to_ascii_lowercase()
+        .split_once('x')
+        .map(|(w, h)| (w.trim().parse::<u32>().ok(), h.trim().parse::<u32>().ok()))?;
+
+    width.zip(height).filter(|(w, h)| *w > 0 && *h > 0)
+}
+
+/// Size in screen pixels: height is corrected for pixel aspect, then image is fitted into box.
+/// Small images aren't upscaled
+pub fn fit_size(
+    width: u32,
+    height: u32,
+    max_width: u32,
+    max_height: u32,
+    aspect: PixelAspect,
+) -> (u32, u32) {
+    let width = width as f64;
+    let height = height as f64 * aspect.x as f64 / aspect.y as f64;
+    let scale = (max_width as f64 / width)
+        .min(max_height as f64 / height)
+        .min(1.0);
+
+    (
+        ((width * scale).round() as u32).max(1),
+        ((height * scale).round() as u32).max(1),
+    )
+}
+
+/// Biggest source image that is decoded, small compressed file can hide huge picture
+const MAX_SOURCE_SIDE: u32 = 8192;
+const MAX_SOURCE_ALLOC: u64 = 256 * 1024 * 1024;
+
 /// Just plain fetching image
-async fn fetch_image_from_url(url_str: &str) -> anyhow::Result<Vec<u8>> {
+async fn fetch_image_from_url(url_str: &str, client: &FetchClient) -> anyhow::Result<Vec<u8>> {
     let url = Url::parse(url_str)?;
 
     debug!("Making request for image: {url_str}");
 
-    let body = reqwest::Client::builder()
-        .user_agent(crate::USER_AGENT)
-        .build()?
-        .get(url)
-        .send()
-        .await?
-        .bytes()
-        .await?
-        .to_vec();
-
-    Ok(body)
+    let response = client.get(url).await?;
+    let limits = &client.limits;
+
+    fetch::read_body(response, limits.max_image_bytes, limits.read_timeout()).await
 }
 
 /// Converts fetched image
-fn convert_image(bytes: Vec<u8>) -> anyhow::Result<Vec<u8>> {
-    let reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+fn convert_image(bytes: Vec<u8>, profile: &ImageProfile) -> anyhow::Result<Vec<u8>> {
+    let mut reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SOURCE_SIDE);
+    limits.max_image_height = Some(MAX_SOURCE_SIDE);
+    limits.max_alloc = Some(MAX_SOURCE_ALLOC);
+    reader.limits(limits);
 
     let mut img = reader.decode()?;
 
-    if img.width() > 320 || img.height() > 240 {
-        img = img.resize(320, 240, image::imageops::FilterType::Gaussian);
+    let (width, height) = fit_size(
+        img.width(),
+        img.height(),
+        profile.max_width,
+        profile.max_height,
+        profile.aspect,
+    );
+    if (width, height) != (img.width(), img.height()) {
+        img = img.resize_exact(width, height, image::imageops::FilterType::Gaussian);
     }
 
+    let palette = match (profile.palette, profile.format) {
+        (Some(palette), _) => palette,
+        // GIF can't have more than 256 colours, so it is always reduced here
+        (None, ImageFormat::Gif) => PaletteOptions::default(),
+        // Any Amiga chipset can show 32 colours
+        (None, ImageFormat::Ilbm(_)) => PaletteOptions::adaptive(32, Dither::FloydSteinberg),
+        (None, ImageFormat::Png) => {
+            return write_image(img, image::ImageFormat::Png, profile.quality);
+        }
+        (None, ImageFormat::Jpeg) => {
+            return write_image(img, image::ImageFormat::Jpeg, profile.quality);
+        }
+    };
+
+    let img = img.to_rgb8();
+
+    match profile.format {
+        ImageFormat::Png => quantize(&img, &palette).write_png(),
+        ImageFormat::Gif => quantize(&img, &palette).write_gif(profile.interlaced),
+        ImageFormat::Jpeg => write_image(
+            DynamicImage::ImageRgb8(quantize(&img, &palette).to_rgb()),
+            image::ImageFormat::Jpeg,
+            profile.quality,
+        ),
+        ImageFormat::Ilbm(mode) => write_ilbm(&img, mode, &palette, profile.aspect),
+    }
+}
+
+fn write_image(
+    img: DynamicImage,
+    format: image::ImageFormat,
+    quality: u8,
+) -> anyhow::Result<Vec<u8>> {
     let outbuf = vec![];
     let mut cursor = Cursor::new(outbuf);
 
-    img.write_to(&mut cursor, image::ImageFormat::Png)?;
+    if format == image::ImageFormat::Jpeg {
+        // JPEG hasn't got alpha channel. Encoder writes baseline JPEG only, that is what old
+        // decoders can show anyway
+        img.to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(
+                &mut cursor,
+                quality.clamp(1, 100),
+            ))?;
+    } else {
+        img.write_to(&mut cursor, format)?;
+    }
     let result = cursor.get_ref().to_vec();
 
     Ok(result)
 }
 
-pub async fn get_converted_picture(url_str: &str) -> anyhow::Result<Vec<u8>> {
-    let bytes = fetch_image_from_url(url_str).await?;
+pub async fn get_converted_picture(
+    url_str: &str,
+    profile: &ImageProfile,
+    client: &FetchClient,
+) -> anyhow::Result<Vec<u8>> {
+    if !profile.enabled {
+        anyhow::bail!("Images are disabled for this profile");
+    }
+
+    let bytes = fetch_image_from_url(url_str, client).await?;
 
-    convert_image(bytes)
+    // Decoding and quantization take long, they shouldn't hold worker that serves other requests
+    let profile = profile.clone();
+    tokio::task::spawn_blocking(move || convert_image(bytes, &profile)).await?
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::server::image::get_converted_picture;
+    use crate::server::clients::{ClientFactory, SessionConfig};
+    use crate::server::fetch::FetchConfig;
+    use crate::server::image::{
+        MAX_SOURCE_SIDE, PixelAspect, convert_image, fit_size, get_converted_picture, parse_size,
+    };
+    use crate::server::profile::{ImageFormat, ImageProfile, default_profile};
+    use crate::server::urlguard::{UrlGuard, UrlGuardConfig};
     use std::io::Write;
+    use std::sync::Arc;
 
     #[tokio::test]
     async fn test_converting_image() -> anyhow::Result<()> {
-        let converted = get_converted_picture("https://cataas.com/cat").await?;
+        let clients = ClientFactory::new(
+            Arc::new(UrlGuard::new(&UrlGuardConfig::default())),
+            FetchConfig::default(),
+            SessionConfig::default(),
+        )?;
+        let converted = get_converted_picture(
+            "https://cataas.com/cat",
+            &default_profile().images,
+            &clients.shared(),
+        )
+        .await?;
 
         let mut file = std::fs::File::create("test.png")?;
         let _ = file.write_all(&converted);
 
         Ok(())
     }
+
+    #[test]
+    fn test_output_formats() -> anyhow::Result<()> {
+        let mut source = std::io::Cursor::new(vec![]);
+        image::RgbImage::from_fn(64, 48, |x, y| image::Rgb([x as u8 * 4, y as u8 * 5, 100]))
+            .write_to(&mut source, image::ImageFormat::Png)?;
+
+        for (format, expected) in [
+            (ImageFormat::Png, image::ImageFormat::Png),
+            (ImageFormat::Gif, image::ImageFormat::Gif),
+            (ImageFormat::Jpeg, image::ImageFormat::Jpeg),
+        ] {
+            let profile = ImageProfile {
+                format,
+                interlaced: true,
+                quality: 40,
+                ..default_profile().images.clone()
+            };
+            let converted = convert_image(source.get_ref().clone(), &profile)?;
+
+            assert_eq!(image::guess_format(&converted)?, expected);
+            let img = image::load_from_memory(&converted)?;
+            assert_eq!((img.width(), img.height()), (64, 48));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_huge_source_is_rejected() -> anyhow::Result<()> {
+        let mut source = std::io::Cursor::new(vec![]);
+        image::GrayImage::new(MAX_SOURCE_SIDE + 1, 1)
+            .write_to(&mut source, image::ImageFormat::Png)?;
+
+        assert!(convert_image(source.into_inner(), &default_profile().images).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sizes_and_aspect() {
+        assert_eq!(
+            fit_size(1024, 768, 320, 240, PixelAspect::SQUARE),
+            (320, 240)
+        );
+        assert_eq!(fit_size(100, 80, 640, 512, PixelAspect::SQUARE), (100, 80));
+
+        // Hires pixels are twice as tall as wide, so half of rows is enough
+        let hires = PixelAspect::parse("1:2").unwrap();
+        assert_eq!(fit_size(640, 480, 640, 256, hires), (640, 240));
+        assert_eq!(fit_size(1280, 960, 640, 256, hires), (640, 240));
+
+        assert_eq!(parse_size("640X512"), Some((640, 512)));
+        assert_eq!(parse_size("0x512"), None);
+        assert_eq!(PixelAspect::parse("0:1"), None);
+        assert_eq!(hires.to_string(), "1:2");
+    }
 }
@@ -0,0 +1,463 @@
+use image::RgbImage;
+
+/// Palette sizes that can be requested, OCS/ECS screens have 2 to 64 colours
+pub const PALETTE_SIZES: [usize; 6] = [2, 4, 16, 32, 64, 256];
+
+/// Default palette of Workbench 2.x and 3.x
+const WORKBENCH_PALETTE: [[u8; 3]; 4] =
+    [[170, 170, 170], [0, 0, 0], [255, 255, 255], [102, 136, 187]];
+
+const BAYER: [[i32; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Big images are sampled, every pixel isn't needed to find their colours
+const MAX_SAMPLES: usize = 65536;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaletteMode {
+    /// Colours are picked from image itself
+    Adaptive,
+    /// Evenly spaced shades of gray
+    Grayscale,
+    /// Always 4 colours of Workbench screen
+    Workbench,
+}
+
+impl PaletteMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "adaptive" => Some(PaletteMode::Adaptive),
+            "grayscale" | "greyscale" | "gray" | "grey" => Some(PaletteMode::Grayscale),
+            "workbench" | "wb" => Some(PaletteMode::Workbench),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    None,
+    FloydSteinberg,
+    /// 4x4 Bayer matrix, gives stable pattern that compresses better
+    Ordered,
+}
+
+impl Dither {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" | "off" => Some(Dither::None),
+            "fs" | "floyd-steinberg" | "floyd" => Some(Dither::FloydSteinberg),
+            "ordered" | "bayer" => Some(Dither::Ordered),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaletteOptions {
+    pub mode: PaletteMode,
+    pub colors: usize,
+    pub dither: Dither,
+}
+
+impl Default for PaletteOptions {
+    fn default() -> Self {
+        Self::adaptive(256, Dither::FloydSteinberg)
+    }
+}
+
+impl PaletteOptions {
+    pub const fn adaptive(colors: usize, dither: Dither) -> Self {
+        Self {
+            mode: PaletteMode::Adaptive,
+            colors,
+            dither,
+        }
+    }
+
+    /// Query parameters override palette of profile, `palette=full` turns reduction off
+    pub fn with_overrides(
+        base: Option<Self>,
+        palette: Option<&str>,
+        colors: Option<&str>,
+        dither: Option<&str>,
+    ) -> anyhow::Result<Option<Self>> {
+        let mut options = match palette {
+            None => base,
+            Some(name) if name.eq_ignore_ascii_case("full") => return Ok(None),
+            Some(name) => Some(Self {
+                mode: PaletteMode::from_name(name)
+                    .ok_or(anyhow::anyhow!("Unknown palette: {name}"))?,
+                ..base.unwrap_or_default()
+            }),
+        };
+
+        if let Some(colors) = colors {
+            let colors = colors
+                .parse::<usize>()
+                .ok()
+                .filter(|c| PALETTE_SIZES.contains(c))
+                .ok_or(anyhow::anyhow!("Unsupported palette size: {colors}"))?;
+
+            options = Some(Self {
+                colors,
+                ..options.unwrap_or_default()
+            });
+        }
+
+        if let Some(dither) = dither {
+            let dither =
+                Dither::from_name(dither).ok_or(anyhow::anyhow!("Unknown dithering: {dither}"))?;
+
+            options = options.map(|o| Self { dither, ..o });
+        }
+
+        Ok(options)
+    }
+}
+
+/// Image where every pixel is index in palette
+#[derive(Debug, Clone)]
+pub struct IndexedImage {
+    pub width: u32,
+    pub height: u32,
+    pub palette: Vec<[u8; 3]>,
+    pub indices: Vec<u8>,
+}
+
+impl IndexedImage {
+    /// Smallest number of bits that fits every index
+    pub fn depth(&self) -> u8 {
+        match self.palette.len() {
+            0..=2 => 1,
+            3..=4 => 2,
+            5..=16 => 4,
+            _ => 8,
+        }
+    }
+
+    pub fn to_rgb(&self) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            let index = self.indices[(y * self.width + x) as usize];
+            image::Rgb(self.palette[index as usize])
+        })
+    }
+
+    /// Indexed PNG with packed pixels, it is much smaller and faster to decode than 24-bit one
+    pub fn write_png(&self) -> anyhow::Result<Vec<u8>> {
+        let depth = self.depth() as usize;
+        let per_byte = 8 / depth;
+        let width = self.width as usize;
+        let row_bytes = width.div_ceil(per_byte);
+
+        let mut data = vec![0u8; row_bytes * self.height as usize];
+        for (y, row) in self.indices.chunks(width.max(1)).enumerate() {
+            for (x, index) in row.iter().enumerate() {
+                let shift = 8 - depth * (x % per_byte + 1);
+                data[y * row_bytes + x / per_byte] |= index << shift;
+            }
+        }
+
+        let mut result = vec![];
+        let mut encoder = png::Encoder::new(&mut result, self.width, self.height);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(match depth {
+            1 => png::BitDepth::One,
+            2 => png::BitDepth::Two,
+            4 => png::BitDepth::Four,
+            _ => png::BitDepth::Eight,
+        });
+        encoder.set_palette(self.palette.concat());
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        writer.finish()?;
+
+        Ok(result)
+    }
+
+    /// Interlaced GIF stores rows in four passes: every 8th row, then the ones between them
+    pub fn write_gif(&self, interlaced: bool) -> anyhow::Result<Vec<u8>> {
+        let width = u16::try_from(self.width)?;
+        let height = u16::try_from(self.height)?;
+
+        let pixels = if interlaced {
+            let rows = self
+                .indices
+                .chunks(self.width.max(1) as usize)
+                .collect::<Vec<_>>();
+            [(0, 8), (4, 8), (2, 4), (1, 2)]
+                .iter()
+                .flat_map(|(start, step)| rows.iter().skip(*start).step_by(*step))
+                .flat_map(|row| row.iter().copied())
+                .collect()
+        } else {
+            self.indices.clone()
+        };
+
+        let mut frame =
+            gif::Frame::from_palette_pixels(width, height, pixels, self.palette.concat(), None);
+        frame.interlaced = interlaced;
+
+        let mut result = vec![];
+        {
+            let mut encoder = gif::Encoder::new(&mut result, width, height, &[])?;
+            encoder.write_frame(&frame)?;
+        }
+
+        Ok(result)
+    }
+}
+
+fn luma(color: [u8; 3]) -> u8 {
+    ((color[0] as u32 * 299 + color[1] as u32 * 587 + color[2] as u32 * 114) / 1000) as u8
+}
+
+struct ColorBox {
+    pixels: Vec<[u8; 3]>,
+    channel: usize,
+    range: u8,
+}
+
+impl ColorBox {
+    fn new(pixels: Vec<[u8; 3]>) -> Self {
+        let (channel, range) = (0..3)
+            .map(|c| {
+                let (min, max) = pixels.iter().fold((u8::MAX, u8::MIN), |(min, max), p| {
+                    (min.min(p[c]), max.max(p[c]))
+                });
+                (c, max.saturating_sub(min))
+            })
+            .max_by_key(|(_, range)| *range)
+            .unwrap_or((0, 0));
+
+        Self {
+            pixels,
+            channel,
+            range,
+        }
+    }
+
+    fn average(&self) -> [u8; 3] {
+        let len = self.pixels.len().max(1) as u64;
+        let sum = self.pixels.iter().fold([0u64; 3], |mut sum, p| {
+            (0..3).for_each(|c| sum[c] += p[c] as u64);
+            sum
+        });
+
+        sum.map(|s| (s / len) as u8)
+    }
+}
+
+/// Median cut, box with widest channel is split in two until there are enough of them
+pub fn median_cut(pixels: &[[u8; 3]], colors: usize) -> Vec<[u8; 3]> {
+    let step = (pixels.len() / MAX_SAMPLES).max(1);
+    let samples = pixels.iter().step_by(step).copied().collect::<Vec<_>>();
+    if samples.is_empty() {
+        return vec![[0, 0, 0]];
+    }
+
+    let mut boxes = vec![ColorBox::new(samples)];
+
+    while boxes.len() < colors {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.range > 0)
+            .max_by_key(|(_, b)| b.range)
+            .map(|(i, _)| i);
+
+        let Some(widest) = widest else {
+            break;
+        };
+
+        let ColorBox {
+            mut pixels,
+            channel,
+            ..
+        } = boxes.swap_remove(widest);
+        pixels.sort_unstable_by_key(|p| p[channel]);
+        let upper = pixels.split_off(pixels.len() / 2);
+
+        boxes.push(ColorBox::new(pixels));
+        boxes.push(ColorBox::new(upper));
+    }
+
+    boxes.iter().map(ColorBox::average).collect()
+}
+
+fn nearest(palette: &[[u8; 3]], color: [i32; 3]) -> u8 {
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, p)| (0..3).map(|c| (p[c] as i32 - color[c]).pow(2)).sum::<i32>())
+        .map(|(i, _)| i as u8)
+        .unwrap_or(0)
+}
+
+/// Reduces image to palette of requested size
+pub fn quantize(img: &RgbImage, options: &PaletteOptions) -> IndexedImage {
+    let colors = options.colors.clamp(2, 256);
+
+    let pixels = img
+        .pixels()
+        .map(|p| match options.mode {
+            PaletteMode::Grayscale => [luma(p.0); 3],
+            _ => p.0,
+        })
+        .collect::<Vec<_>>();
+
+    let palette = match options.mode {
+        PaletteMode::Adaptive => median_cut(&pixels, colors),
+        PaletteMode::Grayscale => (0..colors)
+            .map(|i| [(i * 255 / (colors - 1)) as u8; 3])
+            .collect(),
+        PaletteMode::Workbench => WORKBENCH_PALETTE.to_vec(),
+    };
+
+    let width = img.width() as usize;
+    let indices = match options.dither {
+        Dither::None => pixels
+            .iter()
+            .map(|p| nearest(&palette, p.map(i32::from)))
+            .collect(),
+        Dither::Ordered => {
+            // Distance between neighbour colours of palette
+            let spread = match options.mode {
+                PaletteMode::Grayscale => 255 / (palette.len() as i32 - 1).max(1),
+                _ => (255.0 / (palette.len() as f32).cbrt()) as i32,
+            };
+
+            pixels
+                .iter()
+                .enumerate()
+                .map(|(i, p)| {
+                    let (x, y) = (i % width, i / width);
+                    let offset = (BAYER[y % 4][x % 4] * 2 + 1 - 16) * spread / 32;
+                    nearest(&palette, p.map(|c| c as i32 + offset))
+                })
+                .collect()
+        }
+        Dither::FloydSteinberg => {
+            let mut buffer = pixels.iter().map(|p| p.map(i32::from)).collect::<Vec<_>>();
+            let mut indices = Vec::with_capacity(buffer.len());
+
+            for i in 0..buffer.len() {
+                let (x, y) = (i % width, i / width);
+                let color = buffer[i].map(|c| c.clamp(0, 255));
+                let index = nearest(&palette, color);
+                indices.push(index);
+
+                let chosen = palette[index as usize];
+                let error = [0, 1, 2].map(|c| color[c] - chosen[c] as i32);
+
+                let mut spread = |dx: isize, dy: usize, weight: i32| {
+                    let nx = x as isize + dx;
+                    if nx < 0 || nx as usize >= width {
+                        return;
+                    }
+
+                    let target = (y + dy) * width + nx as usize;
+                    if let Some(pixel) = buffer.get_mut(target) {
+                        (0..3).for_each(|c| pixel[c] += error[c] * weight / 16);
+                    }
+                };
+
+                spread(1, 0, 7);
+                spread(-1, 1, 3);
+                spread(0, 1, 5);
+                spread(1, 1, 1);
+            }
+
+            indices
+        }
+    };
+
+    IndexedImage {
+        width: img.width(),
+        height: img.height(),
+        palette,
+        indices,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient() -> RgbImage {
+        RgbImage::from_fn(64, 16, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 16) as u8, 128])
+        })
+    }
+
+    #[test]
+    fn test_palette_sizes_and_dithering() {
+        let img = gradient();
+
+        for dither in [Dither::None, Dither::FloydSteinberg, Dither::Ordered] {
+            for colors in PALETTE_SIZES {
+                let indexed = quantize(&img, &PaletteOptions::adaptive(colors, dither));
+                assert!(indexed.palette.len() <= colors);
+                assert_eq!(indexed.indices.len(), 64 * 16);
+                assert!(
+                    indexed
+                        .indices
+                        .iter()
+                        .all(|i| (*i as usize) < indexed.palette.len())
+                );
+            }
+        }
+
+        let workbench = PaletteOptions {
+            mode: PaletteMode::Workbench,
+            ..PaletteOptions::default()
+        };
+        assert_eq!(quantize(&img, &workbench).palette, WORKBENCH_PALETTE);
+
+        let gray = quantize(
+            &img,
+            &PaletteOptions {
+                mode: PaletteMode::Grayscale,
+                colors: 4,
+                dither: Dither::Ordered,
+            },
+        );
+        assert_eq!(gray.depth(), 2);
+        assert!(gray.palette.iter().all(|p| p[0] == p[1] && p[1] == p[2]));
+
+        let png = image::load_from_memory(&gray.write_png().unwrap()).unwrap();
+        assert_eq!(png.to_rgb8(), gray.to_rgb());
+        for interlaced in [false, true] {
+            let gif = image::load_from_memory(&gray.write_gif(interlaced).unwrap()).unwrap();
+            assert_eq!(gif.to_rgb8(), gray.to_rgb());
+        }
+    }
+
+    #[test]
+    fn test_palette_overrides() {
+        let profile = Some(PaletteOptions::adaptive(16, Dither::Ordered));
+
+        assert_eq!(
+            PaletteOptions::with_overrides(profile, None, None, None).unwrap(),
+            profile
+        );
+        assert_eq!(
+            PaletteOptions::with_overrides(profile, Some("full"), None, None).unwrap(),
+            None
+        );
+        assert_eq!(
+            PaletteOptions::with_overrides(None, Some("grey"), Some("4"), Some("none")).unwrap(),
+            Some(PaletteOptions {
+                mode: PaletteMode::Grayscale,
+                colors: 4,
+                dither: Dither::None,
+            })
+        );
+        assert_eq!(
+            PaletteOptions::with_overrides(None, None, Some("32"), None).unwrap(),
+            Some(PaletteOptions::adaptive(32, Dither::FloydSteinberg))
+        );
+        assert!(PaletteOptions::with_overrides(None, None, Some("7"), None).is_err());
+        assert!(PaletteOptions::with_overrides(None, Some("ham"), None, None).is_err());
+    }
+}
@@ -1,81 +1,254 @@
+pub mod charset;
+pub mod clients;
+pub mod fetch;
 pub mod image;
+pub mod profile;
+pub mod proxypool;
+pub mod ratelimit;
 pub mod search;
 pub mod simplifier;
+pub mod urlguard;
 
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
 
 use axum::Extension;
-use axum::extract::Query;
-use axum::http::header;
-use axum::response::{Html, IntoResponse};
-use axum::{Router, routing::get};
+use axum::body::Bytes;
+use axum::extract::{Query, RawQuery};
+use axum::http::StatusCode;
+use axum::http::{HeaderMap, header};
+use axum::middleware;
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::{
+    Router,
+    routing::{get, post},
+};
 use log::{debug, info, warn};
 use serde::Deserialize;
-use serpapi_search_rust::serp_api_search::SerpApiSearch;
 use templr::Template;
 use tower_http::services::{ServeDir, ServeFile};
 use tower_http::trace::TraceLayer;
 use url::Url;
 
 use crate::AppConfig;
-use crate::server::image::get_converted_picture;
-use crate::server::search::view::{build_error_page, build_home_page, serp_result_page};
-use crate::server::search::{SearchEngine, SearchProvider};
-use crate::server::simplifier::{process_page, proxy_page};
+use crate::server::charset::OutputCharset;
+use crate::server::clients::{ClientFactory, FetchClient, SESSION_COOKIE};
+use crate::server::image::ilbm::file_name;
+use crate::server::image::palette::PaletteOptions;
+use crate::server::image::{ImageConfig, PixelAspect, get_converted_picture, parse_size};
+use crate::server::profile::{
+    ASPECT_COOKIE, CHARSET_COOKIE, IMAGE_SIZE_COOKIE, ImageFormat, ImageProfile, OutputProfile,
+    PROFILE_COOKIE, find_profile, profile_page, resolve_charset, resolve_images, resolve_profile,
+};
+use crate::server::proxypool::{ProxyPool, proxy_status_page};
+use crate::server::ratelimit::{ClientRateLimiter, rate_limit_middleware};
+use crate::server::search::SearchEngine;
+use crate::server::search::view::{
+    ProviderChoice, build_error_page, build_home_page, serp_result_page,
+};
+use crate::server::simplifier::cache::PageCache;
+use crate::server::simplifier::forms::{
+    FORM_RELAY_PATH, form_params, get_form_url, parse_form_data, take_action,
+};
+use crate::server::simplifier::paging::split_pages;
+use crate::server::simplifier::{
+    PageNav, SimplifierConfig, params_query, process_form_post, process_page, proxy_page,
+};
+use crate::server::urlguard::UrlGuard;
 
 #[derive(Clone, Debug, Deserialize)]
-struct ConvertPngRequest {
+struct ConvertRequest {
     pub url: String,
+    pub profile: Option<String>,
+    /// `png`, `gif` or `jpeg`
+    pub format: Option<String>,
+    /// JPEG quality, 1 to 100
+    pub quality: Option<u8>,
+    /// Only GIF can be interlaced
+    pub interlaced: Option<bool>,
+    /// Box that image is fitted into, capped by `[images]` limits
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Pixel aspect of screen, like `1:2`
+    pub aspect: Option<String>,
+    /// `adaptive`, `grayscale`, `workbench` or `full`
+    pub palette: Option<String>,
+    pub colors: Option<String>,
+    /// `fs`, `ordered` or `none`
+    pub dither: Option<String>,
+}
+
+impl ConvertRequest {
+    /// Image settings of profile with the ones from query applied on top
+    fn image_profile(
+        &self,
+        base: &ImageProfile,
+        limits: &ImageConfig,
+    ) -> anyhow::Result<ImageProfile> {
+        let size = match (self.width, self.height) {
+            (None, None) => None,
+            (width, height) => Some((
+                width.unwrap_or(base.max_width),
+                height.unwrap_or(base.max_height),
+            )),
+        };
+        let aspect = match &self.aspect {
+            Some(aspect) => Some(
+                PixelAspect::parse(aspect)
+                    .ok_or(anyhow::anyhow!("Unknown pixel aspect: {aspect}"))?,
+            ),
+            None => None,
+        };
+
+        let format = match &self.format {
+            Some(name) => ImageFormat::from_name(name)
+                .ok_or(anyhow::anyhow!("Unknown image format: {name}"))?,
+            None => base.format,
+        };
+
+        Ok(ImageProfile {
+            format,
+            quality: self.quality.unwrap_or(base.quality).clamp(1, 100),
+            interlaced: self.interlaced.unwrap_or(base.interlaced),
+            palette: PaletteOptions::with_overrides(
+                base.palette,
+                self.palette.as_deref(),
+                self.colors.as_deref(),
+                self.dither.as_deref(),
+            )?,
+            ..base.with_size(size, aspect, limits)
+        })
+    }
+}
+
+/// Parameters of request that links to our other pages should keep
+fn kept_params(query_params: &HashMap<String, String>) -> Vec<(String, String)> {
+    ["profile", "charset"]
+        .into_iter()
+        .filter_map(|k| Some((k.to_string(), query_params.get(k)?.clone())))
+        .collect()
+}
+
+/// Page transcoded to charset of user's browser
+fn html_response(charset: OutputCharset, html: String) -> Response {
+    (
+        axum::response::AppendHeaders([(header::CONTENT_TYPE, charset.content_type())]),
+        charset.encode(&html),
+    )
+        .into_response()
 }
 
 #[derive(Clone)]
-pub struct Server<A: SearchProvider, B: SearchProvider> {
+pub struct Server {
     pub host: String,
     pub port: u16,
-    pub search_service: Arc<SearchEngine<A, B>>,
+    pub search_service: Arc<SearchEngine>,
     pub base_path: Url,
-    pub api_key: String,
+    pub proxy_pool: Arc<ProxyPool>,
+    pub rate_limiter: Arc<ClientRateLimiter>,
+    pub image_rate_limiter: Arc<ClientRateLimiter>,
+    pub simplifier: SimplifierConfig,
+    pub page_cache: Arc<PageCache>,
+    pub admin_token: Option<String>,
+    pub clients: Arc<ClientFactory>,
+    pub images: ImageConfig,
 }
 
 #[derive(Clone)]
-pub struct Context<A: SearchProvider, B: SearchProvider> {
-    pub search_service: Arc<SearchEngine<A, B>>,
+pub struct Context {
+    pub search_service: Arc<SearchEngine>,
     pub base_path: String,
-    pub serpapi: Arc<SerpApiSearch>,
+    pub proxy_pool: Arc<ProxyPool>,
+    pub simplifier: SimplifierConfig,
+    pub page_cache: Arc<PageCache>,
+    pub admin_token: Option<String>,
+    pub clients: Arc<ClientFactory>,
+    pub images: ImageConfig,
 }
 
-impl<A: SearchProvider, B: SearchProvider> Server<A, B> {
-    pub fn new(app_config: AppConfig, search_service: SearchEngine<A, B>) -> Self {
-        Server {
+impl Server {
+    pub fn new(
+        app_config: AppConfig,
+        search_service: SearchEngine,
+        proxy_pool: Arc<ProxyPool>,
+    ) -> anyhow::Result<Self> {
+        let url_guard = Arc::new(UrlGuard::new(&app_config.url_guard));
+        let clients = ClientFactory::new(url_guard, app_config.fetch, app_config.sessions)?;
+
+        Ok(Server {
             host: app_config.host.clone(),
             port: app_config.port,
-            search_service: Arc::new(search_service.clone()),
+            search_service: Arc::new(search_service),
             base_path: app_config.base_path.clone(),
-            api_key: app_config.api_key,
-        }
+            proxy_pool,
+            rate_limiter: Arc::new(ClientRateLimiter::new(
+                app_config.client_rate_limit,
+                &app_config.trusted_proxies,
+            )),
+            image_rate_limiter: Arc::new(ClientRateLimiter::new(
+                app_config.image_rate_limit,
+                &app_config.trusted_proxies,
+            )),
+            simplifier: app_config.simplifier,
+            page_cache: Arc::new(PageCache::new(app_config.page_cache)),
+            admin_token: app_config.admin_token.filter(|t| !t.is_empty()),
+            clients: Arc::new(clients),
+            images: app_config.images,
+        })
     }
 
     pub async fn start(&self) -> anyhow::Result<()> {
         let context = Arc::new(Context {
             search_service: self.search_service.clone(),
             base_path: self.base_path.to_string(),
-            serpapi: Arc::new(serpapi_search_rust::serp_api_search::SerpApiSearch::new(
-                "google".to_string(),
-                HashMap::new(),
-                self.api_key.clone(),
-            )),
+            proxy_pool: self.proxy_pool.clone(),
+            simplifier: self.simplifier.clone(),
+            page_cache: self.page_cache.clone(),
+            admin_token: self.admin_token.clone(),
+            clients: self.clients.clone(),
+            images: self.images.clone(),
         });
 
+        // Pages that make requests to upstream are limited, image converter has own bucket
+        // because every image of simplified page is a separate request
+        let mut limited: Router = axum::Router::new()
+            .route_service("/browse/", get(Self::browse_handler))
+            .route_service("/", get(Self::root_path_handler));
+
+        // Without kept forms nothing points to relay, so it isn't mounted at all
+        if self.simplifier.keep_forms {
+            limited = limited.route_service(
+                FORM_RELAY_PATH,
+                get(Self::form_relay_get).post(Self::form_relay_post),
+            );
+        }
+
+        let limited = limited.route_layer(middleware::from_fn_with_state(
+            self.rate_limiter.clone(),
+            rate_limit_middleware,
+        ));
+
+        let images: Router = axum::Router::new()
+            .route_service("/convert", get(Self::convert_handler))
+            // Pages simplified before /convert existed
+            .route_service("/convert.png", get(Self::convert_handler))
+            .route_layer(middleware::from_fn_with_state(
+                self.image_rate_limiter.clone(),
+                rate_limit_middleware,
+            ));
+
         let router: Router = axum::Router::new()
             .nest_service(
                 "/static",
                 ServeDir::new("assets/static/")
                     .not_found_service(ServeFile::new("assets/404.html")),
             )
-            .route("/convert.png", get(Self::convert_png))
-            .route_service("/browse/", get(Self::browse_handler))
-            .route_service("/", get(Self::root_path_handler))
+            .route("/status/proxies", get(Self::proxy_status_handler))
+            .route("/profile/", get(Self::profile_handler))
+            .route("/admin/purge", post(Self::purge_handler))
+            .merge(limited)
+            .merge(images)
             .fallback_service(ServeFile::new("assets/404.html"))
             .layer(TraceLayer::new_for_http())
             .layer(Extension(context));
@@ -85,64 +258,445 @@ impl<A: SearchProvider, B: SearchProvider> Server<A, B> {
 
         info!("Started web server: http://{}:{}", self.host, self.port);
 
-        axum::serve(listener, router.into_make_service()).await?;
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await?;
 
         Ok(())
     }
 
     async fn browse_handler(
+        headers: HeaderMap,
         Query(query_params): Query<HashMap<String, String>>,
-        Extension(ext): Extension<Arc<Context<A, B>>>,
+        Extension(ext): Extension<Arc<Context>>,
     ) -> impl IntoResponse {
         let url = query_params.get("url").cloned().unwrap_or("".to_string());
+        let profile = resolve_profile(&headers, query_params.get("profile").map(String::as_str));
+        let charset = resolve_charset(
+            &headers,
+            query_params.get("charset").map(String::as_str),
+            profile,
+        );
 
         let ext = Arc::clone(&ext);
+        let (client, session_cookie) = Self::session_client(&ext, &headers);
 
-        let content = if url.is_empty() {
-            "<h1>Welcome to BoingSearch Simplifier!</h1><p>Enter url and press 'GO' button</p>"
-                .to_string()
+        let (content, nav) = if url.is_empty() {
+            (
+                "<h1>Welcome to BoingSearch Simplifier!</h1><p>Enter url and press 'GO' button</p>"
+                    .to_string(),
+                None,
+            )
         } else {
-            match process_page(url.clone(), format!("{}browse/", ext.base_path.clone())).await {
-                Ok(e) => e,
+            let page = match client {
+                Ok(client) => {
+                    process_page(
+                        url.clone(),
+                        format!("{}browse/", ext.base_path.clone()),
+                        &Self::page_profile(&ext, &headers, profile),
+                        &ext.page_cache,
+                        &client,
+                        ext.simplifier.keep_forms,
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            };
+
+            match page {
+                Ok(page) => Self::select_page(&ext, page, &query_params),
+                Err(e) => (format!("<h1>Error happens</h1><p>{e}</p>"), None),
+            }
+        };
+
+        let result = match proxy_page(url, content, nav, profile, &kept_params(&query_params)) {
+            Ok(c) => c,
+            Err(e) => format!("<h1>Error happens</h1><p>{e}</p>"),
+        };
+
+        (
+            axum::response::AppendHeaders(session_cookie.map(|c| (header::SET_COOKIE, c))),
+            html_response(charset, result),
+        )
+            .into_response()
+    }
+
+    /// Client with visitor's cookie jar. Visitor gets new session on first visit of simplifier,
+    /// its cookie is returned as well. Jar is made only when browser sends cookie back, so
+    /// clients that ignore cookies can't push real visitors out of sessions
+    fn session_client(
+        ext: &Context,
+        headers: &HeaderMap,
+    ) -> (anyhow::Result<FetchClient>, Option<String>) {
+        let session = ClientFactory::session_id(headers);
+        let new_session = (ext.clients.sessions_enabled() && session.is_none())
+            .then(ClientFactory::new_session_id);
+        let session_cookie = new_session
+            .as_ref()
+            .map(|id| format!("{SESSION_COOKIE}={id}; Path=/; HttpOnly; SameSite=Lax"));
+
+        let client = match &session {
+            Some(session) => ext.clients.for_session(Some(session)),
+            None => Ok(ext.clients.shared()),
+        };
+
+        (client, session_cookie)
+    }
+
+    /// GET forms just open resulting url in simplifier
+    async fn form_relay_get(
+        headers: HeaderMap,
+        RawQuery(query): RawQuery,
+        Extension(ext): Extension<Arc<Context>>,
+    ) -> Response {
+        let query = query.unwrap_or_default();
+        let params = form_params(query.as_bytes());
+        let (profile, charset) = Self::form_profile(&headers, &params);
+        let fields = parse_form_data(query.as_bytes(), charset);
+
+        match take_action(fields) {
+            Ok((action, fields)) => {
+                let target = get_form_url(action, &fields);
+                Redirect::to(&format!(
+                    "{}browse/?url={}{}",
+                    ext.base_path,
+                    urlencoding::encode(target.as_str()),
+                    params_query(&params)
+                ))
+                .into_response()
+            }
+            Err(e) => Self::form_error(charset, profile, e),
+        }
+    }
+
+    /// Profile and charset of page that form was sent from
+    fn form_profile(
+        headers: &HeaderMap,
+        params: &[(String, String)],
+    ) -> (&'static OutputProfile, OutputCharset) {
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.as_str())
+        };
+        let profile = resolve_profile(headers, param("profile"));
+
+        (profile, resolve_charset(headers, param("charset"), profile))
+    }
+
+    /// Profile with image sizes chosen by user, simplified pages have them in `img` tags
+    fn page_profile(ext: &Context, headers: &HeaderMap, profile: &OutputProfile) -> OutputProfile {
+        OutputProfile {
+            images: resolve_images(headers, profile, &ext.images),
+            ..profile.clone()
+        }
+    }
+
+    async fn form_relay_post(
+        headers: HeaderMap,
+        Extension(ext): Extension<Arc<Context>>,
+        body: Bytes,
+    ) -> Response {
+        let params = form_params(&body);
+        let (profile, charset) = Self::form_profile(&headers, &params);
+
+        let (action, fields) = match take_action(parse_form_data(&body, charset)) {
+            Ok(form) => form,
+            Err(e) => return Self::form_error(charset, profile, e),
+        };
+
+        let (client, session_cookie) = Self::session_client(&ext, &headers);
+        let page = match client {
+            Ok(client) => {
+                process_form_post(
+                    action.clone(),
+                    fields,
+                    format!("{}browse/", ext.base_path),
+                    &Self::page_profile(&ext, &headers, profile),
+                    &client,
+                    ext.simplifier.keep_forms,
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+
+        let content = page.unwrap_or_else(|e| format!("<h1>Error happens</h1><p>{e}</p>"));
+        let result = match proxy_page(action.to_string(), content, None, profile, &params) {
+            Ok(c) => c,
+            Err(e) => format!("<h1>Error happens</h1><p>{e}</p>"),
+        };
+
+        (
+            axum::response::AppendHeaders(session_cookie.map(|c| (header::SET_COOKIE, c))),
+            html_response(charset, result),
+        )
+            .into_response()
+    }
+
+    fn form_error(
+        charset: OutputCharset,
+        profile: &'static OutputProfile,
+        e: anyhow::Error,
+    ) -> Response {
+        let page = build_error_page(format!("Cannot send this form: {e}"), profile)
+            .unwrap_or("<h1>Internal error</h1>".to_string());
+
+        (StatusCode::BAD_REQUEST, html_response(charset, page)).into_response()
+    }
+
+    /// Picks part of long page that was asked with `page` param, `all` shows whole page
+    fn select_page(
+        ext: &Context,
+        page: String,
+        query_params: &HashMap<String, String>,
+    ) -> (String, Option<PageNav>) {
+        let pages = match split_pages(&page, ext.simplifier.page_size) {
+            Ok(pages) if pages.len() > 1 => pages,
+            _ => return (page, None),
+        };
+
+        let total = pages.len();
+        let browse_path = format!("{}browse/", ext.base_path);
+        let params = kept_params(query_params);
+
+        if query_params.get("all").is_some_and(|a| a == "1") {
+            let nav = PageNav {
+                current: 1,
+                total,
+                showing_all: true,
+                browse_path,
+                params,
+            };
+
+            return (page, Some(nav));
+        }
+
+        let current = query_params
+            .get("page")
+            .and_then(|p| p.parse::<usize>().ok())
+            .unwrap_or(1)
+            .clamp(1, total);
+
+        let nav = PageNav {
+            current,
+            total,
+            showing_all: false,
+            browse_path,
+            params,
+        };
+
+        (pages[current - 1].clone(), Some(nav))
+    }
+
+    /// Keeps chosen profile and charset in cookies, `auto` returns detection by User-Agent
+    async fn profile_handler(
+        headers: HeaderMap,
+        Query(query_params): Query<HashMap<String, String>>,
+        Extension(ext): Extension<Arc<Context>>,
+    ) -> Response {
+        let name = query_params.get("name");
+        let charset = query_params.get("charset");
+        let size = query_params.get("size");
+        let aspect = query_params.get("aspect");
+
+        if name.is_none() && charset.is_none() && size.is_none() && aspect.is_none() {
+            let profile = resolve_profile(&headers, None);
+            let page = profile_page(
+                profile,
+                resolve_charset(&headers, None, profile),
+                resolve_images(&headers, profile, &ext.images),
+            );
+            let result = match page.render(&()) {
+                Ok(c) => c,
                 Err(e) => format!("<h1>Error happens</h1><p>{e}</p>"),
+            };
+
+            return Html(result).into_response();
+        }
+
+        let mut cookies = Vec::new();
+
+        if let Some(name) = name {
+            cookies.push(match find_profile(name) {
+                Some(profile) => format!(
+                    "{PROFILE_COOKIE}={}; Path=/; Max-Age=31536000",
+                    profile.name
+                ),
+                None => format!("{PROFILE_COOKIE}=; Path=/; Max-Age=0"),
+            });
+        }
+
+        if let Some(charset) = charset {
+            cookies.push(match OutputCharset::from_label(charset) {
+                Some(charset) => format!(
+                    "{CHARSET_COOKIE}={}; Path=/; Max-Age=31536000",
+                    charset.label()
+                ),
+                None => format!("{CHARSET_COOKIE}=; Path=/; Max-Age=0"),
+            });
+        }
+
+        if let Some(size) = size {
+            cookies.push(match parse_size(size) {
+                Some((width, height)) => {
+                    format!("{IMAGE_SIZE_COOKIE}={width}x{height}; Path=/; Max-Age=31536000")
+                }
+                None => format!("{IMAGE_SIZE_COOKIE}=; Path=/; Max-Age=0"),
+            });
+        }
+
+        if let Some(aspect) = aspect {
+            cookies.push(match PixelAspect::parse(aspect) {
+                Some(aspect) => format!("{ASPECT_COOKIE}={aspect}; Path=/; Max-Age=31536000"),
+                None => format!("{ASPECT_COOKIE}=; Path=/; Max-Age=0"),
+            });
+        }
+
+        (
+            axum::response::AppendHeaders(cookies.into_iter().map(|c| (header::SET_COOKIE, c))),
+            Redirect::to("/"),
+        )
+            .into_response()
+    }
+
+    /// Request has `Authorization: Bearer <admin_token>` header, always false without the token
+    fn is_admin(ext: &Context, headers: &HeaderMap) -> bool {
+        ext.admin_token.as_ref().is_some_and(|token| {
+            headers
+                .get(header::AUTHORIZATION)
+                .and_then(|h| h.to_str().ok())
+                .and_then(|h| h.strip_prefix("Bearer "))
+                .is_some_and(|given| given.trim() == token)
+        })
+    }
+
+    /// Drops cached pages: single one with `url` param or everything.
+    /// Needs `Authorization: Bearer <admin_token>` header
+    /// Admin endpoints are disabled when there is no `admin_token` in config
+    async fn purge_handler(
+        headers: HeaderMap,
+        Query(query_params): Query<HashMap<String, String>>,
+        Extension(ext): Extension<Arc<Context>>,
+    ) -> Response {
+        if !Self::is_admin(&ext, &headers) {
+            return (StatusCode::FORBIDDEN, "Forbidden\n").into_response();
+        }
+
+        let url = match query_params.get("url").map(|u| Url::parse(u)) {
+            Some(Ok(url)) => Some(url),
+            Some(Err(e)) => {
+                return (StatusCode::BAD_REQUEST, format!("Bad url: {e}\n")).into_response();
             }
+            None => None,
         };
 
-        let result = match proxy_page(url, content).render(&()) {
+        let purged = ext.page_cache.purge(url.as_ref());
+        info!("Purged {purged} cached pages");
+
+        format!("Purged {purged} cached pages\n").into_response()
+    }
+
+    /// Needs `Authorization: Bearer <admin_token>` header, proxy hosts aren't shown to visitors
+    async fn proxy_status_handler(
+        headers: HeaderMap,
+        Extension(ext): Extension<Arc<Context>>,
+    ) -> Response {
+        if !Self::is_admin(&ext, &headers) {
+            return (StatusCode::FORBIDDEN, "Forbidden\n").into_response();
+        }
+
+        let result = match proxy_status_page(ext.proxy_pool.status()).render(&()) {
             Ok(c) => c,
             Err(e) => format!("<h1>Error happens</h1><p>{e}</p>"),
         };
 
-        Html(result)
+        Html(result).into_response()
     }
 
-    async fn convert_png(request: Query<ConvertPngRequest>) -> impl IntoResponse {
-        match get_converted_picture(&request.url).await {
+    async fn convert_handler(
+        headers: HeaderMap,
+        request: Query<ConvertRequest>,
+        Extension(ext): Extension<Arc<Context>>,
+    ) -> impl IntoResponse {
+        let profile = resolve_profile(&headers, request.profile.as_deref());
+        let images =
+            request.image_profile(&resolve_images(&headers, profile, &ext.images), &ext.images);
+        let format = images
+            .as_ref()
+            .map_or(profile.images.format, |images| images.format);
+        let content_type = format.mime();
+        // Saved IFF gets name of original image instead of "convert"
+        let disposition = matches!(format, ImageFormat::Ilbm(_)).then(|| {
+            (
+                header::CONTENT_DISPOSITION,
+                format!("inline; filename=\"{}\"", file_name(&request.url)),
+            )
+        });
+
+        let client = ext
+            .clients
+            .for_session(ClientFactory::session_id(&headers).as_deref());
+        let picture = match (client, images) {
+            (Ok(client), Ok(images)) => get_converted_picture(&request.url, &images, &client).await,
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        };
+
+        match picture {
             Err(e) => {
                 warn!("Image converting error: {e}");
 
                 (
-                    axum::response::AppendHeaders([(header::CONTENT_TYPE, "image/png")]),
+                    axum::response::AppendHeaders([(header::CONTENT_TYPE, content_type)]),
+                    axum::response::AppendHeaders(None),
                     Vec::new(),
                 )
             }
             Ok(response) => (
-                axum::response::AppendHeaders([(header::CONTENT_TYPE, "image/png")]),
+                axum::response::AppendHeaders([(header::CONTENT_TYPE, content_type)]),
+                axum::response::AppendHeaders(disposition),
                 response,
             ),
         }
     }
 
+    fn provider_choices(ext: &Context) -> Vec<ProviderChoice> {
+        ext.search_service
+            .registry
+            .providers()
+            .iter()
+            .map(|p| ProviderChoice {
+                name: p.name.clone(),
+                title: p.title.clone(),
+                searches_left: None,
+            })
+            .collect()
+    }
+
     async fn root_path_handler(
+        headers: HeaderMap,
         Query(query_params): Query<HashMap<String, String>>,
-        Extension(ext): Extension<Arc<Context<A, B>>>,
+        Extension(ext): Extension<Arc<Context>>,
     ) -> impl IntoResponse {
         let ext = Arc::clone(&ext);
+        let profile = resolve_profile(&headers, query_params.get("profile").map(String::as_str));
+        let charset = resolve_charset(
+            &headers,
+            query_params.get("charset").map(String::as_str),
+            profile,
+        );
         let q = query_params.get("q");
-        let premium = query_params
-            .get("premium")
-            .unwrap_or(&"".to_string())
-            .clone();
+        let provider = query_params
+            .get("provider")
+            .filter(|p| !p.is_empty())
+            .cloned();
+        let offset = query_params
+            .get("offset")
+            .and_then(|o| o.parse::<u32>().ok())
+            .unwrap_or(0);
 
         let result = match q {
             Some(query) => {
@@ -150,29 +704,39 @@ impl<A: SearchProvider, B: SearchProvider> Server<A, B> {
 
                 let result = ext
                     .search_service
-                    .first_search(query.clone(), premium)
+                    .first_search(query.clone(), provider, offset)
                     .await;
 
-                result.and_then(|result| serp_result_page(query.clone(), result))
+                result.and_then(|result| {
+                    serp_result_page(
+                        query.clone(),
+                        result,
+                        Self::provider_choices(&ext),
+                        profile,
+                        kept_params(&query_params),
+                    )
+                })
             }
             None => {
-                let serpapi_left = ext.serpapi.account().await.ok();
-
-                debug!("SerpApi left: {serpapi_left:?}");
+                let mut providers = Self::provider_choices(&ext);
+                for choice in providers.iter_mut() {
+                    if let Some(entry) = ext.search_service.registry.get(&choice.name) {
+                        choice.searches_left = entry.provider.searches_left().await;
+                    }
+                }
 
-                let serpapi_left = serpapi_left
-                    .and_then(|o| o.get("total_searches_left").cloned())
-                    .and_then(|n| n.as_u64());
+                debug!("Providers: {providers:?}");
 
-                build_home_page(serpapi_left.unwrap_or(0))
+                build_home_page(providers, profile)
             }
         };
 
-        match result {
-            Ok(r) => Html(r),
-            Err(e) => build_error_page(e.to_string())
-                .map(Html)
-                .unwrap_or(Html("<h1>Internal error</h1>".to_string())),
-        }
+        let page = match result {
+            Ok(r) => r,
+            Err(e) => build_error_page(e.to_string(), profile)
+                .unwrap_or("<h1>Internal error</h1>".to_string()),
+        };
+
+        html_response(charset, page)
     }
 }
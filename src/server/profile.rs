@@ -0,0 +1,456 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::LazyLock;
+
+use axum::http::HeaderMap;
+use axum::http::header::{COOKIE, USER_AGENT};
+use templr::{templ, templ_ret};
+
+use crate::server::charset::OutputCharset;
+use crate::server::image::ilbm::{ILBM_MIME, IlbmMode};
+use crate::server::image::palette::{Dither, PaletteOptions};
+use crate::server::image::{ImageConfig, PixelAspect, parse_size};
+
+/// Cookie that keeps profile chosen by user
+pub const PROFILE_COOKIE: &str = "boing_profile";
+/// Cookie that keeps output charset chosen by user
+pub const CHARSET_COOKIE: &str = "boing_charset";
+/// Cookie that keeps the biggest image size chosen by user, like `640x512`
+pub const IMAGE_SIZE_COOKIE: &str = "boing_image_size";
+/// Cookie that keeps pixel aspect of user's screen, like `1:2`
+pub const ASPECT_COOKIE: &str = "boing_aspect";
+
+/// Screen modes that are offered on profile page: name, image box and pixel aspect
+const SCREEN_MODES: &[(&str, u32, u32, PixelAspect)] = &[
+    ("PAL lores", 320, 256, PixelAspect::SQUARE),
+    ("NTSC lores", 320, 200, PixelAspect { x: 10, y: 11 }),
+    ("PAL hires", 640, 256, PixelAspect { x: 1, y: 2 }),
+    ("NTSC hires", 640, 200, PixelAspect { x: 5, y: 11 }),
+    ("PAL hires interlaced", 640, 512, PixelAspect::SQUARE),
+    (
+        "NTSC hires interlaced",
+        640,
+        400,
+        PixelAspect { x: 10, y: 11 },
+    ),
+    ("RTG 800x600", 800, 600, PixelAspect::SQUARE),
+    ("RTG 1024x768", 1024, 768, PixelAspect::SQUARE),
+];
+
+/// Tags that every profile is based on
+const BASE_TAGS: &[&str] = &[
+    "a",
+    "br",
+    "ol",
+    "li",
+    "p",
+    "small",
+    "font",
+    "b",
+    "strong",
+    "i",
+    "em",
+    "blockquote",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "img",
+    "ul",
+    "pre",
+    "code",
+    "dl",
+    "dt",
+    "dd",
+    "hr",
+    "center",
+    "sup",
+    "sub",
+    "table",
+    "caption",
+    "tr",
+    "th",
+    "td",
+];
+
+/// How tables are shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableMode {
+    /// HTML 3.2 tables with borders
+    Html,
+    /// Rows become lines of text with cells separated by `|`
+    Flatten,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Gif,
+    Jpeg,
+    /// IFF ILBM for datatypes and native Amiga viewers
+    Ilbm(IlbmMode),
+}
+
+impl ImageFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "png" => Some(ImageFormat::Png),
+            "gif" => Some(ImageFormat::Gif),
+            "jpeg" | "jpg" => Some(ImageFormat::Jpeg),
+            "iff" | "ilbm" => Some(ImageFormat::Ilbm(IlbmMode::Indexed)),
+            "ham6" => Some(ImageFormat::Ilbm(IlbmMode::Ham6)),
+            "ham8" => Some(ImageFormat::Ilbm(IlbmMode::Ham8)),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Gif => "gif",
+            ImageFormat::Jpeg => "jpeg",
+            ImageFormat::Ilbm(IlbmMode::Indexed) => "iff",
+            ImageFormat::Ilbm(IlbmMode::Ham6) => "ham6",
+            ImageFormat::Ilbm(IlbmMode::Ham8) => "ham8",
+        }
+    }
+
+    pub fn mime(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Gif => "image/gif",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Ilbm(_) => ILBM_MIME,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImageProfile {
+    /// Text only browsers get no images at all
+    pub enabled: bool,
+    pub format: ImageFormat,
+    pub max_width: u32,
+    pub max_height: u32,
+    /// Colours are reduced when set, browsers on OCS/ECS screens remap true colour badly
+    pub palette: Option<PaletteOptions>,
+    /// JPEG quality, 1 to 100
+    pub quality: u8,
+    /// Interlaced GIF shows up while it is still loading
+    pub interlaced: bool,
+    /// Images on simplified pages get "save as IFF" link
+    pub save_iff: bool,
+    /// Images are squeezed for screens with non-square pixels
+    pub aspect: PixelAspect,
+}
+
+impl ImageProfile {
+    /// Image box and aspect chosen by user, server limits still apply
+    pub fn with_size(
+        &self,
+        size: Option<(u32, u32)>,
+        aspect: Option<PixelAspect>,
+        limits: &ImageConfig,
+    ) -> Self {
+        let (max_width, max_height) = size.unwrap_or((self.max_width, self.max_height));
+
+        Self {
+            max_width: max_width.min(limits.max_width).max(1),
+            max_height: max_height.min(limits.max_height).max(1),
+            aspect: aspect.unwrap_or(self.aspect),
+            ..self.clone()
+        }
+    }
+
+    /// Simplified pages have image sizes in them, so they differ for every layout
+    pub fn layout_key(&self) -> String {
+        format!("{}x{}@{}", self.max_width, self.max_height, self.aspect)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputProfile {
+    pub name: &'static str,
+    pub title: &'static str,
+    /// Tags that are kept in simplified pages
+    pub tags: HashSet<&'static str>,
+    /// Tags that are replaced with other ones
+    pub tag_mapping: HashMap<&'static str, &'static str>,
+    pub images: ImageProfile,
+    pub tables: TableMode,
+    /// Charset that is used when user hasn't chosen one
+    pub charset: OutputCharset,
+}
+
+fn tags(exclude: &[&str]) -> HashSet<&'static str> {
+    BASE_TAGS
+        .iter()
+        .copied()
+        .filter(|t| !exclude.contains(t))
+        .collect()
+}
+
+fn default_mapping() -> HashMap<&'static str, &'static str> {
+    HashMap::from([("strong", "b"), ("em", "i")])
+}
+
+fn images(format: ImageFormat, max_width: u32, max_height: u32) -> ImageProfile {
+    ImageProfile {
+        enabled: true,
+        format,
+        max_width,
+        max_height,
+        palette: None,
+        quality: 75,
+        interlaced: false,
+        save_iff: true,
+        aspect: PixelAspect::SQUARE,
+    }
+}
+
+static PROFILES: LazyLock<Vec<OutputProfile>> = LazyLock::new(|| {
+    vec![
+        OutputProfile {
+            name: "default",
+            title: "Generic",
+            tags: tags(&[]),
+            tag_mapping: default_mapping(),
+            images: images(ImageFormat::Png, 320, 240),
+            tables: TableMode::Html,
+            charset: OutputCharset::Latin1,
+        },
+        OutputProfile {
+            name: "ibrowse",
+            title: "IBrowse",
+            tags: tags(&[]),
+            tag_mapping: default_mapping(),
+            images: images(ImageFormat::Png, 480, 360),
+            tables: TableMode::Html,
+            charset: OutputCharset::Latin1,
+        },
+        OutputProfile {
+            name: "aweb",
+            title: "AWeb",
+            tags: tags(&[]),
+            tag_mapping: default_mapping(),
+            images: images(ImageFormat::Gif, 400, 300),
+            tables: TableMode::Html,
+            charset: OutputCharset::Latin1,
+        },
+        OutputProfile {
+            name: "voyager",
+            title: "Voyager",
+            tags: tags(&[]),
+            tag_mapping: default_mapping(),
+            images: images(ImageFormat::Png, 480, 360),
+            tables: TableMode::Html,
+            charset: OutputCharset::Latin1,
+        },
+        OutputProfile {
+            name: "mosaic",
+            title: "Mosaic",
+            // HTML 2.0 hasn't got these
+            tags: tags(&["font", "small", "center", "sup", "sub"]),
+            tag_mapping: default_mapping(),
+            images: ImageProfile {
+                palette: Some(PaletteOptions::adaptive(16, Dither::FloydSteinberg)),
+                interlaced: true,
+                save_iff: false,
+                ..images(ImageFormat::Gif, 240, 180)
+            },
+            tables: TableMode::Flatten,
+            charset: OutputCharset::Latin1,
+        },
+        OutputProfile {
+            name: "lynx",
+            title: "Lynx",
+            tags: tags(&["font", "small", "img", "center"]),
+            tag_mapping: default_mapping(),
+            images: ImageProfile {
+                enabled: false,
+                ..images(ImageFormat::Gif, 0, 0)
+            },
+            tables: TableMode::Flatten,
+            charset: OutputCharset::Utf8,
+        },
+    ]
+});
+
+pub fn profiles() -> &'static [OutputProfile] {
+    &PROFILES
+}
+
+pub fn find_profile(name: &str) -> Option<&'static OutputProfile> {
+    profiles()
+        .iter()
+        .find(|p| p.name.eq_ignore_ascii_case(name))
+}
+
+pub fn default_profile() -> &'static OutputProfile {
+    &profiles()[0]
+}
+
+/// Guesses profile by browser name in User-Agent
+pub fn sniff_profile(user_agent: &str) -> Option<&'static OutputProfile> {
+    let user_agent = user_agent.to_lowercase();
+
+    profiles()
+        .iter()
+        .skip(1)
+        .find(|p| user_agent.contains(p.name))
+}
+
+pub fn cookie_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(';'))
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+}
+
+/// Profile requested explicitly, then user's choice from cookie, then User-Agent
+pub fn resolve_profile(headers: &HeaderMap, requested: Option<&str>) -> &'static OutputProfile {
+    requested
+        .and_then(find_profile)
+        .or_else(|| cookie_value(headers, PROFILE_COOKIE).and_then(find_profile))
+        .or_else(|| {
+            headers
+                .get(USER_AGENT)
+                .and_then(|ua| ua.to_str().ok())
+                .and_then(sniff_profile)
+        })
+        .unwrap_or(default_profile())
+}
+
+/// Charset requested explicitly, then user's choice from cookie, then profile's one
+pub fn resolve_charset(
+    headers: &HeaderMap,
+    requested: Option<&str>,
+    profile: &OutputProfile,
+) -> OutputCharset {
+    requested
+        .and_then(OutputCharset::from_label)
+        .or_else(|| cookie_value(headers, CHARSET_COOKIE).and_then(OutputCharset::from_label))
+        .unwrap_or(profile.charset)
+}
+
+/// Image settings of profile with user's size and aspect from cookies
+pub fn resolve_images(
+    headers: &HeaderMap,
+    profile: &OutputProfile,
+    limits: &ImageConfig,
+) -> ImageProfile {
+    profile.images.with_size(
+        cookie_value(headers, IMAGE_SIZE_COOKIE).and_then(parse_size),
+        cookie_value(headers, ASPECT_COOKIE).and_then(PixelAspect::parse),
+        limits,
+    )
+}
+
+pub fn profile_page(
+    current: &'static OutputProfile,
+    charset: OutputCharset,
+    images: ImageProfile,
+) -> templ_ret!['static] {
+    templ! {
+        <html>
+            <head>
+                <title>BoingSearch Browser Profile</title>
+            </head>
+            <body>
+                <a href="/">Back to the root!</a>
+                <h2>Browser profile</h2>
+                <p>Profile controls HTML tags, images and charset of pages. Current one: <b>{current.title}</b></p>
+                <ul>
+                    #for profile in profiles() {
+                        <li><a href={format!("/profile/?name={}", profile.name)}>{profile.title}</a></li>
+                    }
+                    <li><a href="/profile/?name=auto">Detect by browser</a></li>
+                </ul>
+                <h2>Charset</h2>
+                <p>Missing characters are replaced with similar ASCII ones. Current one: <b>{charset.label()}</b></p>
+                <ul>
+                    #for charset in OutputCharset::ALL {
+                        <li><a href={format!("/profile/?charset={}", charset.label())}>{charset.label()}</a></li>
+                    }
+                    <li><a href="/profile/?charset=auto">Same as profile</a></li>
+                </ul>
+                <h2>Screen</h2>
+                <p>Images are made to fit the screen and are squeezed for non-square pixels. Current size: <b>{format!("{}x{}", images.max_width, images.max_height)}</b>, pixel aspect: <b>{images.aspect.to_string()}</b></p>
+                <ul>
+                    #for (name, width, height, aspect) in SCREEN_MODES {
+                        <li><a href={format!("/profile/?size={width}x{height}&aspect={aspect}")}>{format!("{name}, {width}x{height}")}</a></li>
+                    }
+                    <li><a href="/profile/?size=auto&aspect=auto">Same as profile</a></li>
+                </ul>
+            </body>
+        </html>
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_resolution() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            USER_AGENT,
+            "Mozilla/4.0 (compatible; AWeb 3.4; AmigaOS)"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(resolve_profile(&headers, None).name, "aweb");
+
+        headers.insert(COOKIE, "session=1; boing_profile=lynx".parse().unwrap());
+        assert_eq!(resolve_profile(&headers, None).name, "lynx");
+        assert_eq!(resolve_profile(&headers, Some("Mosaic")).name, "mosaic");
+        assert_eq!(resolve_profile(&headers, Some("unknown")).name, "lynx");
+
+        assert_eq!(resolve_profile(&HeaderMap::new(), None).name, "default");
+
+        let lynx = find_profile("lynx").unwrap();
+        assert_eq!(resolve_charset(&headers, None, lynx), OutputCharset::Utf8);
+        headers.insert(COOKIE, "boing_charset=macintosh".parse().unwrap());
+        assert_eq!(
+            resolve_charset(&headers, None, lynx),
+            OutputCharset::MacRoman
+        );
+        assert_eq!(
+            resolve_charset(&headers, Some("iso-8859-1"), lynx),
+            OutputCharset::Latin1
+        );
+    }
+
+    #[test]
+    fn test_image_preferences() {
+        let limits = ImageConfig {
+            max_width: 800,
+            max_height: 600,
+        };
+        let mut headers = HeaderMap::new();
+        let aweb = find_profile("aweb").unwrap();
+
+        let images = resolve_images(&headers, aweb, &limits);
+        assert_eq!((images.max_width, images.max_height), (400, 300));
+        assert_eq!(images.aspect, PixelAspect::SQUARE);
+
+        headers.insert(
+            COOKIE,
+            "boing_image_size=640x256; boing_aspect=1:2"
+                .parse()
+                .unwrap(),
+        );
+        let images = resolve_images(&headers, aweb, &limits);
+        assert_eq!(images.layout_key(), "640x256@1:2");
+
+        // Server limits can't be exceeded
+        let images = images.with_size(Some((1280, 1024)), None, &limits);
+        assert_eq!((images.max_width, images.max_height), (800, 600));
+    }
+}
@@ -0,0 +1,469 @@
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use log::{debug, info, warn};
+use rand::Rng;
+use reqwest::{ClientBuilder, Proxy};
+use serde::{Deserialize, Serialize};
+use templr::{templ, templ_ret};
+use url::Url;
+
+/// `[proxy_pool]` section of config.toml
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProxyPoolConfig {
+    /// Url that is requested through every proxy by health probes
+    #[serde(default = "default_probe_url")]
+    pub probe_url: Url,
+    /// 0 disables background health probes
+    #[serde(default = "default_probe_interval_secs")]
+    pub probe_interval_secs: u64,
+    /// Proxy is ejected after this count of failures in a row
+    #[serde(default = "default_max_failures")]
+    pub max_failures: u32,
+    /// How long ejected proxy isn't used if probes don't bring it back earlier
+    #[serde(default = "default_eject_secs")]
+    pub eject_secs: i64,
+}
+
+fn default_probe_url() -> Url {
+    Url::parse("https://lite.duckduckgo.com/lite/").expect("Valid probe url")
+}
+
+fn default_probe_interval_secs() -> u64 {
+    300
+}
+
+fn default_max_failures() -> u32 {
+    3
+}
+
+fn default_eject_secs() -> i64 {
+    1800
+}
+
+impl Default for ProxyPoolConfig {
+    fn default() -> Self {
+        Self {
+            probe_url: default_probe_url(),
+            probe_interval_secs: default_probe_interval_secs(),
+            max_failures: default_max_failures(),
+            eject_secs: default_eject_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct ProxyStats {
+    successes: u64,
+    failures: u64,
+    consecutive_failures: u32,
+    /// Moving average of request latency
+    avg_latency_ms: Option<f64>,
+    last_error: Option<String>,
+    /// Timestamp till proxy is ejected because of failures
+    ejected_until: i64,
+    /// Timestamp till proxy is cooling down after upstream blocked it
+    cooldown_until: i64,
+}
+
+impl ProxyStats {
+    fn is_available(&self, now: i64) -> bool {
+        self.ejected_until <= now && self.cooldown_until <= now
+    }
+
+    /// Fast and reliable proxies are selected more often
+    fn weight(&self) -> f64 {
+        let latency = self.avg_latency_ms.unwrap_or(1000.0);
+        let reliability = 1.0 / (1.0 + self.consecutive_failures as f64).powi(2);
+
+        reliability * 1000.0 / (latency + 100.0)
+    }
+}
+
+/// Proxy state as it's shown on status page
+#[derive(Debug, Clone)]
+pub struct ProxyStatus {
+    pub name: String,
+    pub state: String,
+    pub successes: u64,
+    pub failures: u64,
+    pub avg_latency_ms: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+/// Proxy list entry: `direct` or url with http, https, socks5 or socks5h scheme
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProxyTarget {
+    Direct,
+    Proxy(Url),
+}
+
+impl FromStr for ProxyTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if s.eq_ignore_ascii_case("direct") {
+            return Ok(ProxyTarget::Direct);
+        }
+
+        // Plain "host:port" was accepted as http proxy before schemes appeared
+        let url = if s.contains("://") {
+            Url::parse(s)?
+        } else {
+            Url::parse(&format!("http://{s}"))?
+        };
+
+        match url.scheme() {
+            "http" | "https" | "socks5" | "socks5h" => Ok(ProxyTarget::Proxy(url)),
+            scheme => anyhow::bail!("Unsupported proxy scheme: {scheme}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ProxyPool {
+    proxies: Vec<ProxyTarget>,
+    stats: Mutex<Vec<ProxyStats>>,
+    config: ProxyPoolConfig,
+}
+
+impl ProxyPool {
+    /// Empty list means direct connections
+    pub fn new(proxies: Vec<String>, config: ProxyPoolConfig) -> anyhow::Result<Self> {
+        let mut proxies = proxies
+            .iter()
+            .map(|p| p.parse::<ProxyTarget>())
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        if proxies.is_empty() {
+            proxies.push(ProxyTarget::Direct);
+        }
+
+        let stats = Mutex::new(vec![ProxyStats::default(); proxies.len()]);
+
+        Ok(Self {
+            proxies,
+            stats,
+            config,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.proxies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.proxies.is_empty()
+    }
+
+    fn with_stats<T>(&self, idx: usize, f: impl FnOnce(&mut ProxyStats) -> T) -> Option<T> {
+        let mut stats = self.stats.lock().ok()?;
+
+        stats.get_mut(idx).map(f)
+    }
+
+    /// Proxy address without credentials - for logs and status page
+    pub fn display_name(&self, idx: usize) -> String {
+        match self.proxies.get(idx) {
+            Some(ProxyTarget::Direct) => "direct".to_string(),
+            Some(ProxyTarget::Proxy(url)) => {
+                let mut url = url.clone();
+                let _ = url.set_username("");
+                let _ = url.set_password(None);
+
+                url.to_string()
+            }
+            None => "unknown".to_string(),
+        }
+    }
+
+    /// Weighted random choice between available proxies
+    pub fn select(&self) -> anyhow::Result<usize> {
+        let now = Utc::now().timestamp();
+        let stats = self
+            .stats
+            .lock()
+            .map_err(|_| anyhow::anyhow!("Proxy pool state is poisoned"))?;
+
+        let candidates = stats
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.is_available(now))
+            .map(|(idx, s)| (idx, s.weight()))
+            .collect::<Vec<_>>();
+
+        let total: f64 = candidates.iter().map(|(_, w)| w).sum();
+        if candidates.is_empty() || total <= 0.0 {
+            anyhow::bail!("All proxies are ejected or cooling down");
+        }
+
+        let mut point = rand::rng().random_range(0.0..total);
+        for (idx, weight) in &candidates {
+            if point < *weight {
+                return Ok(*idx);
+            }
+            point -= weight;
+        }
+
+        Ok(candidates[candidates.len() - 1].0)
+    }
+
+    /// Client builder that sends requests through selected proxy
+    pub fn client_builder(&self, idx: usize) -> anyhow::Result<ClientBuilder> {
+        match self.proxies.get(idx) {
+            Some(ProxyTarget::Direct) => Ok(reqwest::Client::builder().no_proxy()),
+            Some(ProxyTarget::Proxy(url)) => {
+                Ok(reqwest::Client::builder().proxy(Proxy::all(url.clone())?))
+            }
+            None => anyhow::bail!("Unknown proxy: {idx}"),
+        }
+    }
+
+    pub fn record_success(&self, idx: usize, latency: Duration) {
+        let latency = latency.as_millis() as f64;
+
+        self.with_stats(idx, |s| {
+            s.successes += 1;
+            s.consecutive_failures = 0;
+            s.ejected_until = 0;
+            s.avg_latency_ms = Some(match s.avg_latency_ms {
+                Some(avg) => avg * 0.8 + latency * 0.2,
+                None => latency,
+            });
+        });
+    }
+
+    pub fn record_failure(&self, idx: usize, error: String) {
+        let name = self.display_name(idx);
+        let max_failures = self.config.max_failures;
+        let eject_until = Utc::now().timestamp() + self.config.eject_secs;
+
+        self.with_stats(idx, |s| {
+            s.failures += 1;
+            s.consecutive_failures += 1;
+            s.last_error = Some(error);
+
+            if s.consecutive_failures >= max_failures && s.ejected_until < eject_until {
+                warn!(
+                    "Proxy {name} ejected after {} failures in a row",
+                    s.consecutive_failures
+                );
+
+                s.ejected_until = eject_until;
+            }
+        });
+    }
+
+    /// Proxy isn't used for some time, even if it passes health probes
+    pub fn cool_down(&self, idx: usize, secs: i64) {
+        warn!(
+            "Proxy {} is cooling down for {secs}s",
+            self.display_name(idx)
+        );
+
+        let until = Utc::now().timestamp() + secs;
+        self.with_stats(idx, |s| s.cooldown_until = until);
+    }
+
+    pub fn status(&self) -> Vec<ProxyStatus> {
+        let now = Utc::now().timestamp();
+        let Ok(stats) = self.stats.lock() else {
+            return Vec::new();
+        };
+
+        stats
+            .iter()
+            .enumerate()
+            .map(|(idx, s)| {
+                let state = if s.cooldown_until > now {
+                    format!("cooling down ({}s left)", s.cooldown_until - now)
+                } else if s.ejected_until > now {
+                    format!("ejected ({}s left)", s.ejected_until - now)
+                } else {
+                    "ok".to_string()
+                };
+
+                ProxyStatus {
+                    name: self.display_name(idx),
+                    state,
+                    successes: s.successes,
+                    failures: s.failures,
+                    avg_latency_ms: s.avg_latency_ms.map(|l| l as u64),
+                    last_error: s.last_error.clone(),
+                }
+            })
+            .collect()
+    }
+
+    async fn probe(&self, idx: usize) -> anyhow::Result<Duration> {
+        let client = self
+            .client_builder(idx)?
+            .user_agent(crate::USER_AGENT)
+            .timeout(Duration::from_secs(10))
+            .build()?;
+
+        let started = Instant::now();
+        client
+            .get(self.config.probe_url.clone())
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(started.elapsed())
+    }
+
+    async fn probe_all(&self) {
+        for idx in 0..self.proxies.len() {
+            match self.probe(idx).await {
+                Ok(latency) => {
+                    debug!("Proxy {} probe: {latency:?}", self.display_name(idx));
+
+                    self.record_success(idx, latency);
+                }
+                Err(e) => {
+                    debug!("Proxy {} probe failed: {e}", self.display_name(idx));
+
+                    self.record_failure(idx, e.to_string());
+                }
+            }
+        }
+
+        let now = Utc::now().timestamp();
+        let available = self
+            .stats
+            .lock()
+            .map(|s| s.iter().filter(|s| s.is_available(now)).count())
+            .unwrap_or(0);
+
+        info!(
+            "Proxy pool: {available} of {} proxies available",
+            self.proxies.len()
+        );
+    }
+
+    /// Starts background health probes
+    pub fn spawn_health_checks(self: &Arc<Self>) {
+        if self.config.probe_interval_secs == 0 {
+            return;
+        }
+
+        let pool = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(pool.config.probe_interval_secs));
+
+            loop {
+                interval.tick().await;
+                pool.probe_all().await;
+            }
+        });
+    }
+}
+
+pub fn proxy_status_page(statuses: Vec<ProxyStatus>) -> templ_ret!['static] {
+    templ! {
+        <html>
+            <head>
+                <title>BoingSearch Proxy Pool</title>
+            </head>
+            <body>
+                <a href="/">Back to the root!</a>
+                <h2>Proxy pool</h2>
+                <table border="1">
+                    <tr>
+                        <th>Proxy</th>
+                        <th>State</th>
+                        <th>Successes</th>
+                        <th>Failures</th>
+                        <th>Latency, ms</th>
+                        <th>Last error</th>
+                    </tr>
+                    #for status in &statuses {
+                        <tr>
+                            <td>{status.name}</td>
+                            <td>{status.state}</td>
+                            <td>{status.successes}</td>
+                            <td>{status.failures}</td>
+                            <td>{status.avg_latency_ms.map(|l| l.to_string()).unwrap_or("-".to_string())}</td>
+                            <td>{status.last_error.clone().unwrap_or("-".to_string())}</td>
+                        </tr>
+                    }
+                </table>
+            </body>
+        </html>
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool() -> ProxyPool {
+        let proxies = ["http://a/", "socks5h://user:secret@b:1080", "direct"];
+
+        ProxyPool::new(
+            proxies.iter().map(|p| p.to_string()).collect(),
+            ProxyPoolConfig::default(),
+        )
+        .expect("Valid proxies")
+    }
+
+    #[test]
+    fn test_unavailable_proxies_skipped() -> anyhow::Result<()> {
+        let pool = pool();
+
+        pool.cool_down(1, 60);
+        for _ in 0..3 {
+            pool.record_failure(0, "Connection refused".to_string());
+        }
+        for _ in 0..10 {
+            assert_eq!(pool.select()?, 2);
+        }
+
+        pool.record_failure(2, "Timeout".to_string());
+        assert!(pool.select().is_ok());
+        pool.record_failure(2, "Timeout".to_string());
+        pool.record_failure(2, "Timeout".to_string());
+        assert!(pool.select().is_err());
+
+        // Successful probe brings ejected proxy back, but not cooling down one
+        pool.record_success(0, Duration::from_millis(200));
+        assert_eq!(pool.select()?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_proxy_targets() -> anyhow::Result<()> {
+        assert_eq!("DIRECT".parse::<ProxyTarget>()?, ProxyTarget::Direct);
+        assert_eq!(
+            "127.0.0.1:3128".parse::<ProxyTarget>()?,
+            ProxyTarget::Proxy(Url::parse("http://127.0.0.1:3128")?)
+        );
+        assert!("socks5://127.0.0.1:1080".parse::<ProxyTarget>().is_ok());
+        assert!("ftp://127.0.0.1".parse::<ProxyTarget>().is_err());
+
+        let pool = ProxyPool::new(Vec::new(), ProxyPoolConfig::default())?;
+        assert_eq!(pool.display_name(pool.select()?), "direct");
+        assert!(pool.client_builder(0)?.build().is_ok());
+        assert!(pool.client_builder(1).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_status_hides_credentials() {
+        let pool = pool();
+        pool.cool_down(1, 60);
+
+        let status = pool.status();
+        assert_eq!(status[1].name, "socks5h://b:1080");
+        assert_eq!(status[2].name, "direct");
+        assert!(status[1].state.starts_with("cooling down"));
+        assert_eq!(status[0].state, "ok");
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::middleware::Next;
+use axum::response::{Html, IntoResponse, Response};
+use ipnet::IpNet;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::server::profile::resolve_profile;
+use crate::server::search::view::build_error_page;
+
+/// Keeps fixed spacing between upstream requests.
+/// Callers are served in arrival order, because tokio's Mutex is fair
+#[derive(Debug)]
+pub struct RequestScheduler {
+    spacing: Duration,
+    next_slot: tokio::sync::Mutex<Instant>,
+}
+
+impl RequestScheduler {
+    pub fn new(spacing: Duration) -> Self {
+        Self {
+            spacing,
+            next_slot: tokio::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Reserves next free slot and sleeps till it comes
+    pub async fn wait_turn(&self) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().await;
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.spacing;
+
+            slot
+        };
+
+        if slot > Instant::now() {
+            info!("Meet request timeout");
+
+            tokio::time::sleep_until(slot).await;
+        }
+    }
+}
+
+/// `[client_rate_limit]` and `[image_rate_limit]` sections of config.toml
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClientRateLimitConfig {
+    /// Sustained rate for single IP, 0 disables limiting
+    #[serde(default = "default_requests_per_minute")]
+    pub requests_per_minute: u32,
+    /// How many requests can be made at once, at least one
+    #[serde(default = "default_burst")]
+    pub burst: u32,
+}
+
+fn default_requests_per_minute() -> u32 {
+    20
+}
+
+fn default_burst() -> u32 {
+    10
+}
+
+impl Default for ClientRateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_minute: default_requests_per_minute(),
+            burst: default_burst(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Per-IP token bucket
+#[derive(Debug)]
+pub struct ClientRateLimiter {
+    config: ClientRateLimitConfig,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+    /// Proxies that tell real address of visitor in `X-Forwarded-For`
+    trusted_proxies: Vec<IpNet>,
+}
+
+/// Buckets of idle clients are dropped when there are more than this
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+impl ClientRateLimiter {
+    pub fn new(config: ClientRateLimitConfig, trusted_proxies: &[String]) -> Self {
+        let trusted_proxies = trusted_proxies
+            .iter()
+            .filter_map(|rule| {
+                let parsed = rule
+                    .parse::<IpNet>()
+                    .or_else(|_| rule.parse::<IpAddr>().map(IpNet::from));
+                if parsed.is_err() {
+                    warn!("Wrong trusted proxy address: {rule}");
+                }
+
+                parsed.ok()
+            })
+            .collect();
+
+        Self {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+            trusted_proxies,
+        }
+    }
+
+    /// Bucket that can't hold a single token would refuse every request
+    fn burst(&self) -> f64 {
+        self.config.burst.max(1) as f64
+    }
+
+    fn refill(&self, bucket: &mut Bucket, now: Instant) {
+        let per_sec = self.config.requests_per_minute as f64 / 60.0;
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+
+        bucket.tokens = (bucket.tokens + elapsed * per_sec).min(self.burst());
+        bucket.updated = now;
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Visitor's address. Requests from trusted proxies are looked through: the last address
+    /// in `X-Forwarded-For` that isn't a trusted proxy is the visitor
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+
+        let mut client = peer;
+        let forwarded = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|h| h.to_str().ok())
+            .flat_map(|h| h.split(','))
+            .collect::<Vec<_>>();
+
+        for addr in forwarded.iter().rev() {
+            let Ok(ip) = addr.trim().parse::<IpAddr>() else {
+                break;
+            };
+
+            client = ip;
+            if !self.is_trusted(ip) {
+                break;
+            }
+        }
+
+        client
+    }
+
+    /// Takes token from client's bucket, false if bucket is empty
+    pub fn try_acquire(&self, ip: IpAddr) -> bool {
+        if self.config.requests_per_minute == 0 {
+            return true;
+        }
+
+        let now = Instant::now();
+        let Ok(mut buckets) = self.buckets.lock() else {
+            return true;
+        };
+
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            buckets.retain(|_, bucket| {
+                self.refill(bucket, now);
+                bucket.tokens < self.burst()
+            });
+        }
+
+        let burst = self.burst();
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        self.refill(bucket, now);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+pub async fn rate_limit_middleware(
+    State(limiter): State<Arc<ClientRateLimiter>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let ip = limiter.client_ip(addr.ip(), request.headers());
+    if limiter.try_acquire(ip) {
+        return next.run(request).await;
+    }
+
+    warn!("Rate limit hit by {ip}");
+
+    let page = build_error_page(
+        "Slow down, please! You are making requests too fast. Wait a minute and try again."
+            .to_string(),
+        resolve_profile(request.headers(), None),
+    )
+    .unwrap_or("<h1>Slow down!</h1>".to_string());
+
+    (StatusCode::TOO_MANY_REQUESTS, Html(page)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scheduler_keeps_spacing() {
+        let spacing = Duration::from_millis(50);
+        let scheduler = Arc::new(RequestScheduler::new(spacing));
+
+        let tasks = (0..4)
+            .map(|_| {
+                let scheduler = scheduler.clone();
+                tokio::spawn(async move {
+                    scheduler.wait_turn().await;
+                    Instant::now()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut turns = Vec::new();
+        for task in tasks {
+            turns.push(task.await.unwrap());
+        }
+        turns.sort();
+
+        for pair in turns.windows(2) {
+            assert!(pair[1] - pair[0] >= spacing - Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_token_bucket_per_client() {
+        let limiter = ClientRateLimiter::new(
+            ClientRateLimitConfig {
+                requests_per_minute: 1,
+                burst: 2,
+            },
+            &[],
+        );
+        let first: IpAddr = "10.0.0.1".parse().unwrap();
+        let second: IpAddr = "10.0.0.2".parse().unwrap();
+
+        assert!(limiter.try_acquire(first));
+        assert!(limiter.try_acquire(first));
+        assert!(!limiter.try_acquire(first));
+        assert!(limiter.try_acquire(second));
+
+        let disabled = ClientRateLimiter::new(
+            ClientRateLimitConfig {
+                requests_per_minute: 0,
+                burst: 0,
+            },
+            &[],
+        );
+        assert!((0..100).all(|_| disabled.try_acquire(first)));
+
+        let no_burst = ClientRateLimiter::new(
+            ClientRateLimitConfig {
+                requests_per_minute: 10,
+                burst: 0,
+            },
+            &[],
+        );
+        assert!(no_burst.try_acquire(first));
+        assert!(!no_burst.try_acquire(first));
+    }
+
+    #[test]
+    fn test_client_behind_trusted_proxy() {
+        let limiter = ClientRateLimiter::new(
+            ClientRateLimitConfig::default(),
+            &["127.0.0.1".to_string(), "10.0.0.0/8".to_string()],
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            "1.2.3.4, 203.0.113.7, 10.0.0.2".parse().unwrap(),
+        );
+
+        let proxy: IpAddr = "127.0.0.1".parse().unwrap();
+        assert_eq!(
+            limiter.client_ip(proxy, &headers),
+            "203.0.113.7".parse::<IpAddr>().unwrap()
+        );
+
+        // Anybody else can't pretend to be a proxy
+        let visitor: IpAddr = "198.51.100.1".parse().unwrap();
+        assert_eq!(limiter.client_ip(visitor, &headers), visitor);
+        assert_eq!(limiter.client_ip(proxy, &HeaderMap::new()), proxy);
+    }
+}
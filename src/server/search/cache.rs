@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use chrono::Utc;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::server::search::SearchResponse;
+
+/// `[search_cache]` section of config.toml
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SearchCacheConfig {
+    /// How long results are valid, 0 disables caching
+    #[serde(default = "default_ttl_secs")]
+    pub ttl_secs: i64,
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+    /// If set - cache will be saved to this file and loaded on start
+    pub persist_path: Option<PathBuf>,
+}
+
+fn default_ttl_secs() -> i64 {
+    600
+}
+
+fn default_max_entries() -> usize {
+    1000
+}
+
+impl Default for SearchCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl_secs: default_ttl_secs(),
+            max_entries: default_max_entries(),
+            persist_path: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq)]
+pub struct CacheKey {
+    pub query: String,
+    pub provider: String,
+    pub offset: u32,
+}
+
+impl CacheKey {
+    pub fn new(query: &str, provider: &str, offset: u32) -> Self {
+        Self {
+            query: normalize_query(query),
+            provider: provider.to_string(),
+            offset,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CacheEntry {
+    stored_at: i64,
+    response: SearchResponse,
+}
+
+/// Same query typed with other case or extra spaces should hit the cache
+pub fn normalize_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|w| w.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub struct SearchCache {
+    config: SearchCacheConfig,
+    entries: Mutex<HashMap<CacheKey, CacheEntry>>,
+    /// Keeps concurrent inserts from writing cache file at same time, held from snapshot to write
+    persist_lock: tokio::sync::Mutex<()>,
+}
+
+impl SearchCache {
+    pub fn new(config: SearchCacheConfig) -> Self {
+        let entries = config
+            .persist_path
+            .as_ref()
+            .and_then(|path| match Self::load(path) {
+                Ok(entries) => {
+                    info!("Loaded {} cached searches from {path:?}", entries.len());
+                    Some(entries)
+                }
+                Err(e) => {
+                    warn!("Cannot load search cache from {path:?}: {e}");
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        Self {
+            config,
+            entries: Mutex::new(entries),
+            persist_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.config.ttl_secs > 0 && self.config.max_entries > 0
+    }
+
+    fn is_fresh(&self, entry: &CacheEntry, now: i64) -> bool {
+        now - entry.stored_at < self.config.ttl_secs
+    }
+
+    fn load(path: &PathBuf) -> anyhow::Result<HashMap<CacheKey, CacheEntry>> {
+        let data = std::fs::read_to_string(path)?;
+        let entries: Vec<(CacheKey, CacheEntry)> = serde_json::from_str(&data)?;
+
+        Ok(entries.into_iter().collect())
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<SearchResponse> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let entries = self.entries.lock().ok()?;
+        let entry = entries.get(key)?;
+
+        if !self.is_fresh(entry, Utc::now().timestamp()) {
+            return None;
+        }
+
+        debug!("Search cache hit: {key:?}");
+
+        Some(entry.response.clone())
+    }
+
+    pub async fn insert(&self, key: CacheKey, response: SearchResponse) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        // Snapshot is taken under the same lock as the write, so older one can't overwrite newer
+        let _guard = self.persist_lock.lock().await;
+
+        let now = Utc::now().timestamp();
+        let snapshot = {
+            let Ok(mut entries) = self.entries.lock() else {
+                return;
+            };
+
+            entries.retain(|_, entry| self.is_fresh(entry, now));
+
+            while entries.len() >= self.config.max_entries {
+                let oldest = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.stored_at)
+                    .map(|(key, _)| key.clone());
+
+                match oldest {
+                    Some(oldest) => entries.remove(&oldest),
+                    None => break,
+                };
+            }
+
+            entries.insert(
+                key,
+                CacheEntry {
+                    stored_at: now,
+                    response,
+                },
+            );
+
+            self.config
+                .persist_path
+                .as_ref()
+                .map(|_| serde_json::to_string(&entries.iter().collect::<Vec<_>>()))
+        };
+
+        if let (Some(path), Some(snapshot)) = (&self.config.persist_path, snapshot)
+            && let Err(e) = self.persist(path, snapshot).await
+        {
+            warn!("Cannot save search cache to {path:?}: {e}");
+        }
+    }
+
+    async fn persist(
+        &self,
+        path: &PathBuf,
+        snapshot: serde_json::Result<String>,
+    ) -> anyhow::Result<()> {
+        let data = snapshot?;
+
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+
+        tokio::fs::write(path, data).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(title: &str) -> SearchResponse {
+        SearchResponse {
+            serp: vec![crate::server::search::Serp {
+                link: "http://aminet.net/".to_string(),
+                displayed_link: "aminet.net".to_string(),
+                title: title.to_string(),
+                snippet: None,
+                engines: Vec::new(),
+            }],
+            inputs: HashMap::new(),
+            offset: 0,
+            next_offset: None,
+            prev_offset: None,
+            provider: "duckduckgo".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cache_eviction_and_normalization() {
+        let cache = SearchCache::new(SearchCacheConfig {
+            ttl_secs: 60,
+            max_entries: 2,
+            persist_path: None,
+        });
+
+        cache
+            .insert(CacheKey::new("Amiga  Games", "ddg", 0), response("first"))
+            .await;
+        cache
+            .insert(CacheKey::new("amiga games", "ddg", 10), response("second"))
+            .await;
+
+        let hit = cache.get(&CacheKey::new(" amiga GAMES ", "ddg", 0));
+        assert_eq!(
+            hit.map(|r| r.serp[0].title.clone()),
+            Some("first".to_string())
+        );
+        assert!(
+            cache
+                .get(&CacheKey::new("amiga games", "serpapi", 0))
+                .is_none()
+        );
+
+        cache
+            .insert(CacheKey::new("aminet", "ddg", 0), response("third"))
+            .await;
+        assert_eq!(cache.entries.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cache_persistence() {
+        let path = std::env::temp_dir().join(format!("boing-cache-{}.json", std::process::id()));
+        let config = SearchCacheConfig {
+            ttl_secs: 60,
+            max_entries: 10,
+            persist_path: Some(path.clone()),
+        };
+
+        SearchCache::new(config.clone())
+            .insert(CacheKey::new("amiga", "ddg", 0), response("stored"))
+            .await;
+
+        let restored = SearchCache::new(config).get(&CacheKey::new("amiga", "ddg", 0));
+        let _ = std::fs::remove_file(path);
+
+        assert_eq!(
+            restored.map(|r| r.serp[0].title.clone()),
+            Some("stored".to_string())
+        );
+    }
+}
@@ -1,12 +1,14 @@
+use crate::server::proxypool::ProxyPool;
+use crate::server::ratelimit::RequestScheduler;
 use crate::server::search::SearchProvider;
 use crate::server::search::Serp;
-use chrono::Utc;
-use itertools::Itertools;
+use crate::server::search::clean_text;
+use crate::server::search::prev_offset;
 use kuchiki::ElementData;
 use kuchiki::NodeDataRef;
+use kuchiki::NodeRef;
 use log::{info, warn};
 use reqwest::Client;
-use reqwest::Proxy;
 use reqwest::header::ACCEPT;
 use reqwest::header::ACCEPT_LANGUAGE;
 use reqwest::header::CONNECTION;
@@ -14,105 +16,249 @@ use reqwest::header::HeaderMap;
 use reqwest::header::REFERER;
 use reqwest::redirect::Policy;
 use std::collections::HashMap;
-use std::str::FromStr;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicI64, AtomicUsize};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use kuchiki::parse_html;
 use kuchiki::traits::*;
 
 use crate::server::search::SearchResponse;
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum DuckDuckError {
+    /// Bot challenge(anomaly) page instead of results
+    Challenge,
+    /// Page is neither results page nor "No results" page
+    UnexpectedPage,
+    MissingLink,
+    MissingHref,
+    BadUrl(String),
+}
+
+impl std::fmt::Display for DuckDuckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DuckDuckError::Challenge => write!(f, "DuckDuckGo asks to solve bot challenge"),
+            DuckDuckError::UnexpectedPage => write!(f, "Unexpected DuckDuckGo page"),
+            DuckDuckError::MissingLink => write!(f, "No link present"),
+            DuckDuckError::MissingHref => write!(f, "No href in link"),
+            DuckDuckError::BadUrl(url) => write!(f, "Cannot parse result url: {url}"),
+        }
+    }
+}
+
+impl std::error::Error for DuckDuckError {}
+
 #[derive(Clone, Debug)]
 pub struct DuckDuckRequester {
-    pub last_access_time: Arc<AtomicI64>,
-    pub req_spacing_secs: i64,
-    pub proxies: Vec<String>,
-    pub proxy_counter: Arc<AtomicUsize>,
+    /// Shared queue that keeps spacing between requests to DuckDuckGo
+    pub scheduler: Arc<RequestScheduler>,
+    pub proxy_pool: Arc<ProxyPool>,
+    /// How long proxy isn't used after bot challenge
+    pub proxy_cooldown_secs: i64,
 }
 
 impl DuckDuckRequester {
-    pub fn new(req_spacing_secs: i64, proxies: Vec<String>) -> DuckDuckRequester {
+    pub fn new(
+        req_spacing_secs: i64,
+        proxy_pool: Arc<ProxyPool>,
+        proxy_cooldown_secs: i64,
+    ) -> DuckDuckRequester {
         Self {
-            last_access_time: Arc::new(AtomicI64::new(0)),
-            req_spacing_secs,
-            proxies,
-            proxy_counter: Arc::new(AtomicUsize::new(Utc::now().timestamp() as usize)),
+            scheduler: Arc::new(RequestScheduler::new(Duration::from_secs(
+                req_spacing_secs.max(0) as u64,
+            ))),
+            proxy_pool,
+            proxy_cooldown_secs,
         }
     }
 
-    fn try_extract_serp(data: Vec<NodeDataRef<ElementData>>) -> anyhow::Result<Serp> {
-        let first_line = data.first().ok_or(anyhow::anyhow!("Data line absent"))?;
-        let snippet_line = data.get(1).ok_or(anyhow::anyhow!("Snippet line absent"));
-        let url_line = data.get(2).ok_or(anyhow::anyhow!("Url line absent"))?;
+    fn is_challenge_page(page: &NodeRef) -> bool {
+        if page.select_first("form#challenge-form").is_ok()
+            || page.select_first(".anomaly-modal__modal").is_ok()
+        {
+            return true;
+        }
+
+        let anomaly_form = page.select("form").into_iter().flatten().any(|form| {
+            form.attributes
+                .borrow()
+                .get("action")
+                .is_some_and(|action| action.contains("anomaly.js"))
+        });
 
-        let display_url = url_line.text_contents();
-        let snippet = snippet_line.map(|s| s.text_contents()).ok();
+        anomaly_form || page.text_contents().contains("bots use DuckDuckGo too")
+    }
 
-        let link = first_line
-            .as_node()
-            .select_first("a")
-            .map_err(|_| anyhow::anyhow!("No link present"))?;
-        let head_text = link.text_contents();
+    fn is_sponsored(rows: &[NodeDataRef<ElementData>]) -> bool {
+        rows.iter().any(|row| {
+            row.attributes
+                .borrow()
+                .get("class")
+                .is_some_and(|c| c.contains("result-sponsored"))
+        }) || rows.iter().any(|row| {
+            row.as_node()
+                .select("a.result-link")
+                .into_iter()
+                .flatten()
+                .any(|a| {
+                    a.attributes
+                        .borrow()
+                        .get("href")
+                        .is_some_and(|href| href.contains("duckduckgo.com/y.js"))
+                })
+        })
+    }
+
+    /// Resolves DuckDuckGo redirect link(`//duckduckgo.com/l/?uddg=...`) to real target
+    fn resolve_link(href: &str) -> Result<String, DuckDuckError> {
+        let url = url::Url::parse("https://lite.duckduckgo.com/")
+            .and_then(|base| base.join(href))
+            .map_err(|_| DuckDuckError::BadUrl(href.to_string()))?;
+
+        let is_redirect = url
+            .host_str()
+            .is_some_and(|h| h.ends_with("duckduckgo.com"))
+            && url.path().starts_with("/l/");
+
+        if !is_redirect {
+            return Ok(url.to_string());
+        }
+
+        url.query_pairs()
+            .find(|(k, _)| k == "uddg")
+            .map(|(_, v)| v.to_string())
+            .ok_or(DuckDuckError::BadUrl(href.to_string()))
+    }
+
+    /// Extracts result from its rows: title with link, optional snippet and displayed url
+    fn try_extract_serp(data: Vec<NodeDataRef<ElementData>>) -> Result<Serp, DuckDuckError> {
+        let link = data
+            .first()
+            .and_then(|line| line.as_node().select_first("a.result-link").ok())
+            .ok_or(DuckDuckError::MissingLink)?;
+        let head_text = clean_text(&link.text_contents());
         let link_attrs = link.attributes.borrow();
-        let dd_url = link_attrs
-            .get("href")
-            .ok_or(anyhow::anyhow!("No href in link"))?;
+        let dd_url = link_attrs.get("href").ok_or(DuckDuckError::MissingHref)?;
 
-        let url = url::Url::from_str(&format!("https:{dd_url}"))?;
-        let query = url
-            .query_pairs()
-            .into_owned()
-            .collect::<HashMap<String, String>>();
+        let target = Self::resolve_link(dd_url)?;
 
-        let target = query.get("uddg").cloned().unwrap_or(dd_url.to_string());
+        let snippet = data
+            .iter()
+            .find_map(|row| row.as_node().select_first("td.result-snippet").ok())
+            .map(|s| clean_text(&s.text_contents()))
+            .filter(|s| !s.is_empty());
 
-        let target = urlencoding::decode(&target)?.to_string();
+        let display_url = data
+            .iter()
+            .find_map(|row| row.as_node().select_first("span.link-text").ok())
+            .map(|s| clean_text(&s.text_contents()))
+            .unwrap_or(target.clone());
 
         Ok(Serp {
             link: target,
             displayed_link: deunicode::deunicode(&display_url),
             title: deunicode::deunicode(&head_text),
             snippet,
+            engines: Vec::new(),
         })
     }
 
-    fn parse_serp_result(page_txt: String) -> anyhow::Result<SearchResponse> {
-        let page = parse_html().one(page_txt.clone());
+    /// Lite version hasn't got any offset in url - it's using form with hidden fields
+    /// for navigation. Collecting them from form of "Next Page" or "Previous Page" button
+    fn parse_nav_form(page: &NodeRef, button: &str) -> Option<HashMap<String, String>> {
+        let forms = page.select("form").ok()?;
+
+        for form in forms {
+            let is_wanted = form.as_node().select("input.navbutton").ok()?.any(|btn| {
+                btn.attributes
+                    .borrow()
+                    .get("value")
+                    .is_some_and(|v| v.contains(button))
+            });
+
+            if !is_wanted {
+                continue;
+            }
+
+            let fields = form
+                .as_node()
+                .select("input[type=hidden]")
+                .ok()?
+                .filter_map(|input| {
+                    let attrs = input.attributes.borrow();
+                    let name = attrs.get("name")?.to_string();
+                    let value = attrs.get("value").unwrap_or("").to_string();
+
+                    Some((name, value))
+                })
+                .collect::<HashMap<String, String>>();
+
+            return Some(fields);
+        }
+
+        None
+    }
+
+    fn parse_serp_result(page_txt: String, offset: u32) -> anyhow::Result<SearchResponse> {
+        let page = parse_html().one(page_txt);
         let mut serp_items: Vec<Serp> = Vec::new();
 
-        if let Ok(tables) = page.select("table") {
-            for table in tables {
-                if table.as_node().select("a.result-link").is_ok()
-                    && let Ok(items) = table.as_node().select("tr")
-                {
-                    let items = items.chunks(4);
-                    for item in &items {
-                        let item = item.collect::<Vec<_>>();
-
-                        match Self::try_extract_serp(item) {
-                            Ok(serp) => serp_items.push(serp),
-                            Err(e) => warn!("Error happens: {e:?}"),
-                        }
-                    }
-                }
+        if Self::is_challenge_page(&page) {
+            return Err(DuckDuckError::Challenge.into());
+        }
+
+        let rows = page
+            .select("table tr")
+            .map_err(|_| DuckDuckError::UnexpectedPage)?
+            .collect::<Vec<_>>();
+
+        // Every result starts from row with result link and takes all rows till the next one
+        let mut results: Vec<Vec<NodeDataRef<ElementData>>> = Vec::new();
+        for row in rows {
+            if row.as_node().select_first("a.result-link").is_ok() {
+                results.push(vec![row]);
+            } else if let Some(result) = results.last_mut() {
+                result.push(row);
+            }
+        }
+
+        if results.is_empty() && !page.text_contents().contains("No results.") {
+            return Err(DuckDuckError::UnexpectedPage.into());
+        }
+
+        for result in results {
+            if Self::is_sponsored(&result) {
+                continue;
+            }
+
+            match Self::try_extract_serp(result) {
+                Ok(serp) => serp_items.push(serp),
+                Err(e) => warn!("Error happens: {e:?}"),
             }
-        } else {
-            anyhow::bail!("Cannot find tables on page");
         }
 
+        let inputs = Self::parse_nav_form(&page, "Next").unwrap_or_default();
+        let next_offset = inputs.get("s").and_then(|s| s.parse::<u32>().ok());
+        // Pages differ in size, without "Previous Page" form step back by size of this one
+        let prev_offset = Self::parse_nav_form(&page, "Previous")
+            .and_then(|form| form.get("s")?.parse::<u32>().ok())
+            .or_else(|| {
+                let page_size = next_offset?.checked_sub(offset).filter(|s| *s > 0)?;
+                prev_offset(offset, page_size)
+            })
+            .or_else(|| prev_offset(offset, offset));
+
         Ok(SearchResponse {
             serp: serp_items,
-            inputs: HashMap::new(),
+            inputs,
+            offset,
+            next_offset,
+            prev_offset,
+            provider: String::new(),
         })
     }
 
-    fn get_next_proxy(&self) -> usize {
-        self.proxy_counter
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
-    }
-
     fn build_client_for_proxy(&self, idx: usize) -> anyhow::Result<Client> {
         let mut headers = HeaderMap::new();
         headers.append(
@@ -123,33 +269,51 @@ impl DuckDuckRequester {
         headers.append(CONNECTION, "close".parse()?);
         headers.append(REFERER, " https://lite.duckduckgo.com/".parse()?);
 
-        let proxy = self
-            .proxies
-            .get(idx % self.proxies.len())
-            .ok_or(anyhow::anyhow!("Proxy required"))?;
-
-        info!("Using proxy: {proxy}");
+        info!("Using proxy: {}", self.proxy_pool.display_name(idx));
 
-        let client = reqwest::Client::builder()
+        let client = self
+            .proxy_pool
+            .client_builder(idx)?
             .cookie_store(true)
             .http1_only()
             .redirect(Policy::limited(2))
             .default_headers(headers)
             .user_agent(crate::USER_AGENT)
-            .proxy(Proxy::http(proxy)?)
-            .proxy(Proxy::https(proxy)?)
             .timeout(Duration::from_secs(10))
             .build()?;
 
         Ok(client)
     }
 
-    async fn make_serp_request_inner(&self, query: String) -> anyhow::Result<SearchResponse> {
-        let query = urlencoding::encode(&query).to_string();
-
-        let pr_id = self.get_next_proxy();
+    async fn make_serp_request_inner(
+        &self,
+        query: String,
+        offset: u32,
+    ) -> anyhow::Result<SearchResponse> {
+        let pr_id = self.proxy_pool.select()?;
         let client = self.build_client_for_proxy(pr_id)?;
 
+        let started = Instant::now();
+        let result = Self::request_pages(&client, query, offset).await;
+
+        match &result {
+            Ok(_) => self.proxy_pool.record_success(pr_id, started.elapsed()),
+            Err(e) if e.downcast_ref::<DuckDuckError>() == Some(&DuckDuckError::Challenge) => {
+                self.proxy_pool.cool_down(pr_id, self.proxy_cooldown_secs)
+            }
+            Err(e) => self.proxy_pool.record_failure(pr_id, e.to_string()),
+        }
+
+        result
+    }
+
+    async fn request_pages(
+        client: &Client,
+        query: String,
+        offset: u32,
+    ) -> anyhow::Result<SearchResponse> {
+        let query = urlencoding::encode(&query).to_string();
+
         let data = client
             .get("https://lite.duckduckgo.com/lite/")
             .send()
@@ -161,37 +325,47 @@ impl DuckDuckRequester {
             .send()
             .await?;
         let page_txt = result.text().await?;
+        let first_page = Self::parse_serp_result(page_txt, 0)?;
 
-        Self::parse_serp_result(page_txt)
-    }
-
-    async fn wait(&self) {
-        let last_access = self
-            .last_access_time
-            .load(std::sync::atomic::Ordering::Acquire);
-
-        let now = Utc::now().timestamp();
-        let diff = now - (last_access);
+        if offset == 0 {
+            return Ok(first_page);
+        }
 
-        if diff < self.req_spacing_secs {
-            info!("Meet request timeout");
+        // No "Next Page" form - no more results
+        let Some(mut form) = first_page.next_offset.map(|_| first_page.inputs) else {
+            return Ok(SearchResponse {
+                serp: Vec::new(),
+                inputs: HashMap::new(),
+                offset,
+                next_offset: None,
+                prev_offset: Some(0),
+                provider: String::new(),
+            });
+        };
+
+        form.insert("s".to_string(), offset.to_string());
+        form.insert("dc".to_string(), (offset + 1).to_string());
 
-            tokio::time::sleep(tokio::time::Duration::from_secs(
-                (self.req_spacing_secs - diff) as u64,
-            ))
-            .await;
-        }
+        let result = client
+            .post("https://lite.duckduckgo.com/lite/")
+            .form(&form)
+            .send()
+            .await?;
+        let page_txt = result.text().await?;
 
-        self.last_access_time
-            .store(Utc::now().timestamp(), std::sync::atomic::Ordering::SeqCst);
+        Self::parse_serp_result(page_txt, offset)
     }
 }
 
 #[async_trait::async_trait]
 impl SearchProvider for DuckDuckRequester {
-    async fn make_serp_request(&self, query: String) -> anyhow::Result<SearchResponse> {
-        self.wait().await;
-        self.make_serp_request_inner(query).await
+    async fn make_serp_request(
+        &self,
+        query: String,
+        offset: u32,
+    ) -> anyhow::Result<SearchResponse> {
+        self.scheduler.wait_turn().await;
+        self.make_serp_request_inner(query, offset).await
     }
 }
 
@@ -201,13 +375,111 @@ async fn test_new_free_serp_provider() -> anyhow::Result<()> {
 
     let app_conf = crate::AppConfig::try_create()?;
 
-    let provider = DuckDuckRequester::new(1, app_conf.proxies);
+    let proxy_pool = Arc::new(ProxyPool::new(app_conf.proxies, app_conf.proxy_pool)?);
+    let provider = DuckDuckRequester::new(1, proxy_pool, app_conf.proxy_cooldown_secs);
 
     let result = provider
-        .make_serp_request("Serp parsing services".to_string())
+        .make_serp_request("Serp parsing services".to_string(), 0)
         .await?;
 
     info!("{result:#?}");
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_fixture(page: &str) -> anyhow::Result<SearchResponse> {
+        DuckDuckRequester::parse_serp_result(page.to_string(), 0)
+    }
+
+    #[test]
+    fn test_parse_results_fixture() -> anyhow::Result<()> {
+        let result = parse_fixture(include_str!("../../../tests/fixtures/ddg_results.html"))?;
+
+        // Ad and broken result are skipped
+        assert_eq!(result.serp.len(), 3);
+
+        assert_eq!(
+            result.serp[0].link,
+            "https://en.wikipedia.org/wiki/Amiga_500?a=1%20b"
+        );
+        assert_eq!(result.serp[0].title, "Amiga 500 - Wikipedia");
+        assert_eq!(
+            result.serp[0].displayed_link,
+            "en.wikipedia.org/wiki/Amiga_500"
+        );
+        assert!(
+            result.serp[0]
+                .snippet
+                .as_ref()
+                .is_some_and(|s| s.starts_with("The Amiga 500, also known"))
+        );
+
+        assert_eq!(result.serp[1].link, "https://www.amigaforever.com/");
+        assert_eq!(result.serp[1].snippet, None);
+
+        assert_eq!(result.serp[2].link, "http://www.amiga-stuff.com/");
+        assert_eq!(result.serp[2].title, "Amiga Stuff -- Cafe");
+
+        assert_eq!(result.next_offset, Some(23));
+        assert_eq!(result.prev_offset, None);
+
+        let second = DuckDuckRequester::parse_serp_result(
+            include_str!("../../../tests/fixtures/ddg_results.html").to_string(),
+            20,
+        )?;
+        assert_eq!(second.prev_offset, Some(17));
+        assert_eq!(result.inputs.get("vqd").map(String::len), Some(32));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_no_results_fixture() -> anyhow::Result<()> {
+        let result = parse_fixture(include_str!("../../../tests/fixtures/ddg_no_results.html"))?;
+
+        assert!(result.serp.is_empty());
+        assert_eq!(result.next_offset, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_anomaly_fixture() {
+        let err = parse_fixture(include_str!("../../../tests/fixtures/ddg_anomaly.html"))
+            .expect_err("Anomaly page isn't results page");
+
+        assert_eq!(
+            err.downcast_ref::<DuckDuckError>(),
+            Some(&DuckDuckError::Challenge)
+        );
+    }
+
+    #[test]
+    fn test_extract_serp_errors() {
+        let page = parse_html().one(
+            r#"<table>
+                <tr><td><a class="result-link">No href</a></td></tr>
+                <tr><td><a class="result-link" href="//duckduckgo.com/l/?rut=1">No target</a></td></tr>
+                <tr><td>No link</td></tr>
+            </table>"#,
+        );
+        let rows = page.select("tr").unwrap().collect::<Vec<_>>();
+
+        assert_eq!(
+            DuckDuckRequester::try_extract_serp(vec![rows[0].clone()]).unwrap_err(),
+            DuckDuckError::MissingHref
+        );
+        assert_eq!(
+            DuckDuckRequester::try_extract_serp(vec![rows[1].clone()]).unwrap_err(),
+            DuckDuckError::BadUrl("//duckduckgo.com/l/?rut=1".to_string())
+        );
+        assert_eq!(
+            DuckDuckRequester::try_extract_serp(vec![rows[2].clone()]).unwrap_err(),
+            DuckDuckError::MissingLink
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use crate::server::search::{
+    SearchProvider, SearchResponse, Serp, clean_text, fetch_serp_page, prev_offset,
+};
+use kuchiki::NodeRef;
+use kuchiki::parse_html;
+use kuchiki::traits::*;
+use log::warn;
+use std::collections::HashMap;
+use url::Url;
+
+const MARGINALIA_PAGE_SIZE: u32 = 10;
+
+#[derive(Clone, Debug)]
+pub struct MarginaliaProvider {
+    pub base_url: Url,
+}
+
+impl MarginaliaProvider {
+    pub fn new(base_url: Url) -> Self {
+        Self { base_url }
+    }
+
+    fn search_url(&self, query: &str, offset: u32) -> anyhow::Result<Url> {
+        let mut url = self.base_url.join("search")?;
+        url.query_pairs_mut().append_pair("query", query);
+
+        if offset >= MARGINALIA_PAGE_SIZE {
+            url.query_pairs_mut()
+                .append_pair("page", &(offset / MARGINALIA_PAGE_SIZE + 1).to_string());
+        }
+
+        Ok(url)
+    }
+
+    fn try_extract_serp(section: &NodeRef) -> anyhow::Result<Serp> {
+        let link = section
+            .select_first("a.title")
+            .map_err(|_| anyhow::anyhow!("No link present"))?;
+        let link_attrs = link.attributes.borrow();
+        let href = link_attrs
+            .get("href")
+            .ok_or(anyhow::anyhow!("No href in link"))?;
+
+        let displayed_link = section
+            .select_first(".url")
+            .map(|u| clean_text(&u.text_contents()))
+            .unwrap_or(href.to_string());
+
+        let snippet = section
+            .select_first("p.description")
+            .map(|p| clean_text(&p.text_contents()))
+            .ok()
+            .filter(|s| !s.is_empty());
+
+        Ok(Serp {
+            link: href.to_string(),
+            displayed_link: deunicode::deunicode(&displayed_link),
+            title: deunicode::deunicode(&clean_text(&link.text_contents())),
+            snippet,
+            engines: Vec::new(),
+        })
+    }
+
+    fn parse_serp_result(page_txt: String, offset: u32) -> anyhow::Result<SearchResponse> {
+        let page = parse_html().one(page_txt);
+        let mut serp_items = Vec::new();
+
+        let sections = page
+            .select("section.search-result")
+            .map_err(|_| anyhow::anyhow!("Cannot select result sections"))?;
+
+        for section in sections {
+            match Self::try_extract_serp(section.as_node()) {
+                Ok(serp) => serp_items.push(serp),
+                Err(e) => warn!("Error happens: {e:?}"),
+            }
+        }
+
+        let next_page = format!("page={}", offset / MARGINALIA_PAGE_SIZE + 2);
+        let has_more = page
+            .select("nav a")
+            .map_err(|_| anyhow::anyhow!("Cannot select navigation"))?
+            .any(|a| {
+                a.attributes
+                    .borrow()
+                    .get("href")
+                    .is_some_and(|href| href.contains(&next_page))
+            });
+
+        Ok(SearchResponse {
+            serp: serp_items,
+            inputs: HashMap::new(),
+            offset,
+            next_offset: has_more.then_some(offset + MARGINALIA_PAGE_SIZE),
+            prev_offset: prev_offset(offset, MARGINALIA_PAGE_SIZE),
+            provider: String::new(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl SearchProvider for MarginaliaProvider {
+    async fn make_serp_request(
+        &self,
+        query: String,
+        offset: u32,
+    ) -> anyhow::Result<SearchResponse> {
+        let page_txt = fetch_serp_page(self.search_url(&query, offset)?).await?;
+
+        Self::parse_serp_result(page_txt, offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_marginalia_fixture() -> anyhow::Result<()> {
+        let page = include_str!("../../../tests/fixtures/marginalia.html").to_string();
+        let result = MarginaliaProvider::parse_serp_result(page, 0)?;
+
+        assert_eq!(result.serp.len(), 2);
+        assert_eq!(result.serp[0].link, "https://www.amigalove.com/");
+        assert_eq!(result.serp[0].title, "AmigaLove - Amiga Computer Community");
+        assert_eq!(result.serp[0].displayed_link, "www.amigalove.com");
+        assert_eq!(result.serp[1].snippet, None);
+        assert_eq!(result.next_offset, Some(MARGINALIA_PAGE_SIZE));
+
+        let last =
+            include_str!("../../../tests/fixtures/marginalia.html").replace("page=2", "page=1");
+        let result = MarginaliaProvider::parse_serp_result(last, 0)?;
+        assert_eq!(result.next_offset, None);
+
+        Ok(())
+    }
+}
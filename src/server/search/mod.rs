@@ -1,42 +1,99 @@
+pub mod cache;
 pub mod duckduckprovider;
+pub mod marginaliaprovider;
+pub mod mojeekprovider;
+pub mod registry;
+pub mod searxngprovider;
 pub mod serpapiprovider;
 pub mod view;
+pub mod wibyprovider;
 
 use censor::Censor;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use log::warn;
+use crate::server::search::cache::{CacheKey, SearchCache};
+use crate::server::search::registry::ProviderRegistry;
+
+use log::{debug, warn};
 use std::collections::HashMap;
+use std::time::Duration;
+use url::Url;
 
-#[derive(Clone, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Serp {
     pub link: String,
     pub displayed_link: String,
     pub title: String,
     pub snippet: Option<String>,
+    /// Underlying engines that found this result, for meta search providers
+    #[serde(default)]
+    pub engines: Vec<String>,
 }
 
-#[derive(Clone, Debug)]
+/// Amount of results we are showing per page
+pub const PAGE_SIZE: u32 = 10;
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct SearchResponse {
     pub serp: Vec<Serp>,
     pub inputs: HashMap<String, String>,
+    /// Offset of the first result on this page
+    pub offset: u32,
+    /// Offset of the next page, if provider knows that there are more results
+    pub next_offset: Option<u32>,
+    /// Offset of the previous page, pages of providers differ in size
+    #[serde(default)]
+    pub prev_offset: Option<u32>,
+    /// Name of provider that made this response, filled by `SearchEngine`
+    pub provider: String,
 }
 
 #[async_trait::async_trait]
-pub trait SearchProvider: Clone + Sync + Send + 'static {
-    /// Make first search request
-    async fn make_serp_request(&self, query: String) -> anyhow::Result<SearchResponse>;
+pub trait SearchProvider: Sync + Send + 'static {
+    /// Make search request, `offset` is amount of results to skip
+    async fn make_serp_request(&self, query: String, offset: u32)
+    -> anyhow::Result<SearchResponse>;
+
+    /// Amount of searches left for providers with limited quota
+    async fn searches_left(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Fetches results page for HTML scraping providers
+pub async fn fetch_serp_page(url: Url) -> anyhow::Result<String> {
+    debug!("Fetching results page: {url}");
+
+    let response = reqwest::Client::builder()
+        .user_agent(crate::USER_AGENT)
+        .timeout(Duration::from_secs(10))
+        .build()?
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(response.text().await?)
+}
+
+/// Previous page for providers with fixed page size, none for the first one
+pub fn prev_offset(offset: u32, page_size: u32) -> Option<u32> {
+    (offset > 0).then(|| offset.saturating_sub(page_size))
 }
 
-#[derive(Clone)]
-pub struct SearchEngine<A: SearchProvider, B: SearchProvider> {
-    pub free: A,
-    pub premium: B,
+/// Collapses whitespace in scraped text
+pub fn clean_text(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub struct SearchEngine {
+    pub registry: ProviderRegistry,
+    pub cache: SearchCache,
     pub censor: Censor,
 }
 
-impl<A: SearchProvider, B: SearchProvider> SearchEngine<A, B> {
-    pub fn new(free: A, premium: B) -> Self {
+impl SearchEngine {
+    pub fn new(registry: ProviderRegistry, cache: SearchCache) -> Self {
         let censor_words = include_str!("../../../assets/censorwords.txt").lines();
         let mut censor = Censor::Sex + Censor::Standard;
         for word in censor_words {
@@ -44,8 +101,8 @@ impl<A: SearchProvider, B: SearchProvider> SearchEngine<A, B> {
         }
 
         Self {
-            free,
-            premium,
+            registry,
+            cache,
             censor,
         }
     }
@@ -53,30 +110,50 @@ impl<A: SearchProvider, B: SearchProvider> SearchEngine<A, B> {
     pub async fn first_search(
         &self,
         query: String,
-        premium: String,
+        provider: Option<String>,
+        offset: u32,
     ) -> anyhow::Result<SearchResponse> {
         if self.censor.check(&query) {
             anyhow::bail!("Your request was denied by internal rules");
         }
 
-        if premium.is_empty() {
-            match self.free.make_serp_request(query.clone()).await {
-                Ok(r) => Ok(r),
-                Err(e) => {
-                    warn!("Error during premium search: {e}");
+        let chain = self.registry.chain(provider.as_deref());
+        let Some(selected) = chain.first() else {
+            anyhow::bail!("No search providers enabled");
+        };
+
+        let cache_key = CacheKey::new(&query, &selected.name, offset);
+        if let Some(cached) = self.cache.get(&cache_key) {
+            return Ok(cached);
+        }
 
-                    self.premium.make_serp_request(query).await
+        let mut last_error = anyhow::anyhow!("No search providers enabled");
+
+        for entry in chain.iter() {
+            match entry
+                .provider
+                .make_serp_request(query.clone(), offset)
+                .await
+            {
+                Ok(mut r) => {
+                    r.provider = entry.name.clone();
+
+                    // Fallback results aren't cached under selected provider, it should be
+                    // asked again as soon as it recovers
+                    if !r.serp.is_empty() && entry.name == selected.name {
+                        self.cache.insert(cache_key, r.clone()).await;
+                    }
+
+                    return Ok(r);
                 }
-            }
-        } else {
-            match self.premium.make_serp_request(query.clone()).await {
-                Ok(r) => Ok(r),
                 Err(e) => {
-                    warn!("Error during premium search: {e}");
+                    warn!("Error during search with {}: {e}", entry.name);
 
-                    self.free.make_serp_request(query).await
+                    last_error = e;
                 }
             }
         }
+
+        Err(last_error)
     }
 }
@@ -0,0 +1,128 @@
+use crate::server::search::{
+    PAGE_SIZE, SearchProvider, SearchResponse, Serp, clean_text, fetch_serp_page, prev_offset,
+};
+use kuchiki::NodeRef;
+use kuchiki::parse_html;
+use kuchiki::traits::*;
+use log::warn;
+use std::collections::HashMap;
+use url::Url;
+
+#[derive(Clone, Debug)]
+pub struct MojeekProvider {
+    pub base_url: Url,
+}
+
+impl MojeekProvider {
+    pub fn new(base_url: Url) -> Self {
+        Self { base_url }
+    }
+
+    fn search_url(&self, query: &str, offset: u32) -> anyhow::Result<Url> {
+        let mut url = self.base_url.join("search")?;
+        url.query_pairs_mut().append_pair("q", query);
+
+        // Mojeek counts results from 1
+        if offset > 0 {
+            url.query_pairs_mut()
+                .append_pair("s", &(offset + 1).to_string());
+        }
+
+        Ok(url)
+    }
+
+    fn try_extract_serp(item: &NodeRef) -> anyhow::Result<Serp> {
+        let link = item
+            .select_first("a.title")
+            .map_err(|_| anyhow::anyhow!("No link present"))?;
+        let link_attrs = link.attributes.borrow();
+        let href = link_attrs
+            .get("href")
+            .ok_or(anyhow::anyhow!("No href in link"))?;
+
+        let displayed_link = item
+            .select_first("p.i")
+            .map(|u| clean_text(&u.text_contents()))
+            .unwrap_or(href.to_string());
+
+        let snippet = item
+            .select_first("p.s")
+            .map(|p| clean_text(&p.text_contents()))
+            .ok()
+            .filter(|s| !s.is_empty());
+
+        Ok(Serp {
+            link: href.to_string(),
+            displayed_link: deunicode::deunicode(&displayed_link),
+            title: deunicode::deunicode(&clean_text(&link.text_contents())),
+            snippet,
+            engines: Vec::new(),
+        })
+    }
+
+    fn parse_serp_result(page_txt: String, offset: u32) -> anyhow::Result<SearchResponse> {
+        let page = parse_html().one(page_txt);
+        let mut serp_items = Vec::new();
+
+        let items = page
+            .select("ul.results-standard > li")
+            .map_err(|_| anyhow::anyhow!("Cannot select results"))?;
+
+        for item in items {
+            match Self::try_extract_serp(item.as_node()) {
+                Ok(serp) => serp_items.push(serp),
+                Err(e) => warn!("Error happens: {e:?}"),
+            }
+        }
+
+        let has_more = page
+            .select(".pagination a")
+            .map_err(|_| anyhow::anyhow!("Cannot select pagination"))?
+            .any(|a| a.text_contents().contains("Next"));
+
+        Ok(SearchResponse {
+            serp: serp_items,
+            inputs: HashMap::new(),
+            offset,
+            next_offset: has_more.then_some(offset + PAGE_SIZE),
+            prev_offset: prev_offset(offset, PAGE_SIZE),
+            provider: String::new(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl SearchProvider for MojeekProvider {
+    async fn make_serp_request(
+        &self,
+        query: String,
+        offset: u32,
+    ) -> anyhow::Result<SearchResponse> {
+        let page_txt = fetch_serp_page(self.search_url(&query, offset)?).await?;
+
+        Self::parse_serp_result(page_txt, offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mojeek_fixture() -> anyhow::Result<()> {
+        let page = include_str!("../../../tests/fixtures/mojeek.html").to_string();
+        let result = MojeekProvider::parse_serp_result(page, 10)?;
+
+        assert_eq!(result.serp.len(), 2);
+        assert_eq!(result.serp[0].link, "https://en.wikipedia.org/wiki/Amiga");
+        assert_eq!(result.serp[0].title, "Amiga - Wikipedia");
+        assert_eq!(
+            result.serp[0].snippet.as_deref(),
+            Some("The Amiga is a family of personal computers introduced by Commodore in 1985.")
+        );
+        assert_eq!(result.serp[1].displayed_link, "www.amigaos.net > about");
+        assert_eq!(result.next_offset, Some(20));
+
+        Ok(())
+    }
+}
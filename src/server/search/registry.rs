@@ -0,0 +1,267 @@
+use std::sync::Arc;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::AppConfig;
+use crate::server::proxypool::ProxyPool;
+use crate::server::search::SearchProvider;
+use crate::server::search::duckduckprovider::DuckDuckRequester;
+use crate::server::search::marginaliaprovider::MarginaliaProvider;
+use crate::server::search::mojeekprovider::MojeekProvider;
+use crate::server::search::searxngprovider::SearxngProvider;
+use crate::server::search::serpapiprovider::SerpApiProvider;
+use crate::server::search::wibyprovider::WibyProvider;
+
+/// Provider entry from `[[providers]]` section of config.toml
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProviderConfig {
+    pub name: String,
+    /// Name that will be shown to user, `name` will be used if absent
+    pub title: Option<String>,
+    /// Providers with higher priority are going first in lists and used by default
+    #[serde(default)]
+    pub priority: i32,
+    /// Names of providers that will be tried (in this order) if this one fails
+    #[serde(default)]
+    pub fallback: Vec<String>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub kind: ProviderKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ProviderKind {
+    DuckDuckGo,
+    SerpApi {
+        /// Global `api_key` will be used if absent
+        api_key: Option<String>,
+    },
+    Wiby {
+        base_url: Option<Url>,
+    },
+    Marginalia {
+        base_url: Option<Url>,
+    },
+    Mojeek {
+        base_url: Option<Url>,
+    },
+    Searxng {
+        base_url: Url,
+    },
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// Providers that we had before registry appears - used when config hasn't got `[[providers]]`
+pub fn default_providers() -> Vec<ProviderConfig> {
+    vec![
+        ProviderConfig {
+            name: "duckduckgo".to_string(),
+            title: Some("DuckDuckGo".to_string()),
+            priority: 10,
+            fallback: vec!["serpapi".to_string()],
+            enabled: true,
+            kind: ProviderKind::DuckDuckGo,
+        },
+        ProviderConfig {
+            name: "serpapi".to_string(),
+            title: Some("SerpAPI(Google)".to_string()),
+            priority: 0,
+            fallback: vec!["duckduckgo".to_string()],
+            enabled: true,
+            kind: ProviderKind::SerpApi { api_key: None },
+        },
+    ]
+}
+
+pub struct RegisteredProvider {
+    pub name: String,
+    pub title: String,
+    pub priority: i32,
+    pub fallback: Vec<String>,
+    pub provider: Box<dyn SearchProvider>,
+}
+
+#[derive(Default)]
+pub struct ProviderRegistry {
+    /// Always sorted by priority
+    providers: Vec<Arc<RegisteredProvider>>,
+}
+
+impl ProviderRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_config(app_config: &AppConfig, proxy_pool: Arc<ProxyPool>) -> anyhow::Result<Self> {
+        let mut registry = Self::new();
+
+        for config in app_config.providers.iter().filter(|p| p.enabled) {
+            let provider: Box<dyn SearchProvider> = match &config.kind {
+                ProviderKind::DuckDuckGo => Box::new(DuckDuckRequester::new(
+                    app_config.rate_limit,
+                    proxy_pool.clone(),
+                    app_config.proxy_cooldown_secs,
+                )),
+                ProviderKind::SerpApi { api_key } => Box::new(SerpApiProvider::new(
+                    api_key.clone().unwrap_or(app_config.api_key.clone()),
+                )),
+                ProviderKind::Wiby { base_url } => Box::new(WibyProvider::new(Self::base_url_or(
+                    base_url,
+                    "https://wiby.me/",
+                )?)),
+                ProviderKind::Marginalia { base_url } => Box::new(MarginaliaProvider::new(
+                    Self::base_url_or(base_url, "https://search.marginalia.nu/")?,
+                )),
+                ProviderKind::Mojeek { base_url } => Box::new(MojeekProvider::new(
+                    Self::base_url_or(base_url, "https://www.mojeek.com/")?,
+                )),
+                ProviderKind::Searxng { base_url } => {
+                    Box::new(SearxngProvider::new(base_url.clone()))
+                }
+            };
+
+            info!("Registering search provider: {}", config.name);
+
+            registry.register(config, provider)?;
+        }
+
+        Ok(registry)
+    }
+
+    fn base_url_or(base_url: &Option<Url>, default: &str) -> anyhow::Result<Url> {
+        match base_url {
+            Some(url) => Ok(url.clone()),
+            None => Ok(Url::parse(default)?),
+        }
+    }
+
+    pub fn register(
+        &mut self,
+        config: &ProviderConfig,
+        provider: Box<dyn SearchProvider>,
+    ) -> anyhow::Result<()> {
+        if self.get(&config.name).is_some() {
+            anyhow::bail!("Search provider {} registered twice", config.name);
+        }
+
+        self.providers.push(Arc::new(RegisteredProvider {
+            name: config.name.clone(),
+            title: config.title.clone().unwrap_or(config.name.clone()),
+            priority: config.priority,
+            fallback: config.fallback.clone(),
+            provider,
+        }));
+        self.providers.sort_by_key(|p| -p.priority);
+
+        Ok(())
+    }
+
+    /// All enabled providers, sorted by priority
+    pub fn providers(&self) -> &[Arc<RegisteredProvider>] {
+        &self.providers
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<RegisteredProvider>> {
+        self.providers.iter().find(|p| p.name == name).cloned()
+    }
+
+    /// Provider with highest priority
+    pub fn default_provider(&self) -> Option<Arc<RegisteredProvider>> {
+        self.providers.first().cloned()
+    }
+
+    /// Chain of providers that should be tried in this order for selected provider
+    pub fn chain(&self, name: Option<&str>) -> Vec<Arc<RegisteredProvider>> {
+        let first = name
+            .and_then(|name| self.get(name))
+            .or_else(|| self.default_provider());
+
+        let Some(first) = first else {
+            return Vec::new();
+        };
+
+        let mut chain = vec![first.clone()];
+        for fallback in &first.fallback {
+            if chain.iter().any(|p| &p.name == fallback) {
+                continue;
+            }
+
+            match self.get(fallback) {
+                Some(p) => chain.push(p),
+                None => warn!("Fallback provider {fallback} is not enabled"),
+            }
+        }
+
+        chain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::search::SearchResponse;
+
+    struct Dummy;
+
+    #[async_trait::async_trait]
+    impl SearchProvider for Dummy {
+        async fn make_serp_request(
+            &self,
+            _query: String,
+            offset: u32,
+        ) -> anyhow::Result<SearchResponse> {
+            Ok(SearchResponse {
+                serp: Vec::new(),
+                inputs: Default::default(),
+                offset,
+                next_offset: None,
+                prev_offset: None,
+                provider: String::new(),
+            })
+        }
+    }
+
+    fn config(name: &str, priority: i32, fallback: &[&str]) -> ProviderConfig {
+        ProviderConfig {
+            name: name.to_string(),
+            title: None,
+            priority,
+            fallback: fallback.iter().map(|f| f.to_string()).collect(),
+            enabled: true,
+            kind: ProviderKind::DuckDuckGo,
+        }
+    }
+
+    #[test]
+    fn test_fallback_chain() -> anyhow::Result<()> {
+        let mut registry = ProviderRegistry::new();
+        registry.register(&config("low", 0, &["high"]), Box::new(Dummy))?;
+        registry.register(
+            &config("high", 5, &["absent", "mid", "high"]),
+            Box::new(Dummy),
+        )?;
+        registry.register(&config("mid", 3, &[]), Box::new(Dummy))?;
+
+        let names = |chain: Vec<Arc<RegisteredProvider>>| {
+            chain.iter().map(|p| p.name.clone()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(names(registry.chain(None)), vec!["high", "mid"]);
+        assert_eq!(names(registry.chain(Some("low"))), vec!["low", "high"]);
+        assert_eq!(names(registry.chain(Some("unknown"))), vec!["high", "mid"]);
+        assert!(
+            registry
+                .register(&config("mid", 1, &[]), Box::new(Dummy))
+                .is_err()
+        );
+
+        Ok(())
+    }
+}
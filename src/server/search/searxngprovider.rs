@@ -0,0 +1,126 @@
+use crate::server::search::{
+    PAGE_SIZE, SearchProvider, SearchResponse, Serp, fetch_serp_page, prev_offset,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use url::Url;
+
+#[derive(Clone, Debug)]
+pub struct SearxngProvider {
+    pub base_url: Url,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct SearxngResult {
+    pub url: String,
+    #[serde(default)]
+    pub title: String,
+    pub content: Option<String>,
+    pub pretty_url: Option<String>,
+    #[serde(default)]
+    pub engines: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct SearxngResponse {
+    #[serde(default)]
+    pub results: Vec<SearxngResult>,
+}
+
+impl SearxngProvider {
+    pub fn new(base_url: Url) -> Self {
+        Self { base_url }
+    }
+
+    fn search_url(&self, query: &str, offset: u32) -> anyhow::Result<Url> {
+        let mut url = self.base_url.join("search")?;
+        url.query_pairs_mut()
+            .append_pair("q", query)
+            .append_pair("format", "json")
+            .append_pair("pageno", &(offset / PAGE_SIZE + 1).to_string());
+
+        Ok(url)
+    }
+
+    fn parse_serp_result(page_txt: &str, offset: u32) -> anyhow::Result<SearchResponse> {
+        let response: SearxngResponse = serde_json::from_str(page_txt)?;
+
+        let serp = response
+            .results
+            .into_iter()
+            .map(|r| Serp {
+                displayed_link: deunicode::deunicode(r.pretty_url.as_ref().unwrap_or(&r.url)),
+                title: deunicode::deunicode(&r.title),
+                snippet: r.content.filter(|c| !c.is_empty()),
+                link: r.url,
+                engines: r.engines,
+            })
+            .collect::<Vec<_>>();
+
+        let next_offset = (!serp.is_empty()).then_some(offset + PAGE_SIZE);
+
+        Ok(SearchResponse {
+            serp,
+            inputs: HashMap::new(),
+            offset,
+            next_offset,
+            prev_offset: prev_offset(offset, PAGE_SIZE),
+            provider: String::new(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl SearchProvider for SearxngProvider {
+    async fn make_serp_request(
+        &self,
+        query: String,
+        offset: u32,
+    ) -> anyhow::Result<SearchResponse> {
+        let page_txt = fetch_serp_page(self.search_url(&query, offset)?).await?;
+
+        Self::parse_serp_result(&page_txt, offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::Query;
+    use axum::routing::get;
+
+    async fn fake_searxng(Query(params): Query<HashMap<String, String>>) -> String {
+        assert_eq!(params.get("format").map(String::as_str), Some("json"));
+        assert_eq!(params.get("q").map(String::as_str), Some("amiga 1200"));
+        assert_eq!(params.get("pageno").map(String::as_str), Some("2"));
+
+        include_str!("../../../tests/fixtures/searxng.json").to_string()
+    }
+
+    #[tokio::test]
+    async fn test_searxng_local_instance() -> anyhow::Result<()> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let router = axum::Router::new().route("/searxng/search", get(fake_searxng));
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let provider = SearxngProvider::new(Url::parse(&format!("http://{addr}/searxng/"))?);
+        let result = provider
+            .make_serp_request("amiga 1200".to_string(), PAGE_SIZE)
+            .await?;
+
+        assert_eq!(result.serp.len(), 2);
+        assert_eq!(
+            result.serp[0].link,
+            "https://en.wikipedia.org/wiki/Amiga_1200"
+        );
+        assert_eq!(result.serp[0].title, "Amiga 1200 - Wikipedia");
+        assert_eq!(result.serp[0].engines, vec!["wikipedia", "duckduckgo"]);
+        assert_eq!(result.serp[1].snippet, None);
+        assert_eq!(result.serp[1].displayed_link, "http://www.a1200.example/");
+        assert_eq!(result.offset, PAGE_SIZE);
+        assert_eq!(result.next_offset, Some(PAGE_SIZE * 2));
+
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use crate::server::search::{SearchProvider, SearchResponse, Serp};
+use crate::server::search::{PAGE_SIZE, SearchProvider, SearchResponse, Serp, prev_offset};
 use log::debug;
 use serde::Deserialize;
 use serpapi_search_rust::serp_api_search::SerpApiSearch;
@@ -47,14 +47,33 @@ impl SerpApiProvider {
 
 #[async_trait::async_trait]
 impl SearchProvider for SerpApiProvider {
-    async fn make_serp_request(&self, query: String) -> anyhow::Result<SearchResponse> {
-        let serp = self.get_serp(query.clone(), None).await?;
+    async fn make_serp_request(
+        &self,
+        query: String,
+        offset: u32,
+    ) -> anyhow::Result<SearchResponse> {
+        let serp = self.get_serp(query.clone(), Some(offset)).await?;
+        let next_offset = (!serp.is_empty()).then_some(offset + PAGE_SIZE);
 
         let mut inputs = HashMap::new();
         inputs.insert("q".to_string(), query);
-        inputs.insert("premium".to_string(), "checked".to_string());
-        inputs.insert("offset".to_string(), "10".to_string());
 
-        Ok(SearchResponse { serp, inputs })
+        Ok(SearchResponse {
+            serp,
+            inputs,
+            offset,
+            next_offset,
+            prev_offset: prev_offset(offset, PAGE_SIZE),
+            provider: String::new(),
+        })
+    }
+
+    async fn searches_left(&self) -> Option<u64> {
+        let search = SerpApiSearch::new("google".to_string(), HashMap::new(), self.api_key.clone());
+        let account = search.account().await.ok()?;
+
+        debug!("SerpApi account: {account:?}");
+
+        account.get("total_searches_left")?.as_u64()
     }
 }
@@ -1,10 +1,27 @@
 use templr::{Template, templ, templ_ret};
 
+use crate::server::profile::OutputProfile;
 use crate::server::search::SearchResponse;
 use crate::server::search::Serp;
+use crate::server::simplifier::params_query;
+use crate::server::simplifier::transform::apply_profile;
 use deunicode::deunicode;
 
-pub fn serp_result_page(query: String, serp_result: SearchResponse) -> anyhow::Result<String> {
+/// Search provider as it's shown in selector
+#[derive(Clone, Debug)]
+pub struct ProviderChoice {
+    pub name: String,
+    pub title: String,
+    pub searches_left: Option<u64>,
+}
+
+pub fn serp_result_page(
+    query: String,
+    serp_result: SearchResponse,
+    providers: Vec<ProviderChoice>,
+    profile: &'static OutputProfile,
+    params: Vec<(String, String)>,
+) -> anyhow::Result<String> {
     let template = templ! {
         <html>
             <head>
@@ -15,15 +32,14 @@ pub fn serp_result_page(query: String, serp_result: SearchResponse) -> anyhow::R
                     <table widht="100%" border="0">
                         <tr widht="100%">
                             <td>
-                                <a href="/"><img src="/static/logo.gif" alt="BoingSearch Logo" /></a>
+                                <a href="/">#render_logo(profile);</a>
                             </td>
                             <td>
                                     Search results for: <input type="text" size="30" name="q" value={query}/><br/><br/>
-                                    #if serp_result.inputs.contains_key("premium") {
-                                        <input type="checkbox" name="premium" checked /> Use SerpAPI for search | <input type="submit" value="Search!"/><br/>
-                                    } else {
-                                        <input type="checkbox" name="premium" /> Use SerpAPI for search | <input type="submit" value="Search!"/><br/>
+                                    #if providers.len() > 1 {
+                                        Search with: #render_provider_select(providers.clone(), serp_result.provider.clone()); |
                                     }
+                                    <input type="submit" value="Search!"/><br/>
                             </td>
                         </tr>
                     </table>
@@ -35,15 +51,24 @@ pub fn serp_result_page(query: String, serp_result: SearchResponse) -> anyhow::R
                         #render_serp_item(item.clone());
                         <br/>
                     }
+                #render_pagination(query.clone(), serp_result.clone(), params.clone());
                 #build_footer();
             </body>
             </html>
     };
 
-    template.render(&())
+    apply_profile(&template.render(&())?, profile)
 }
 
-pub fn build_home_page(serpapi_left: u64) -> anyhow::Result<String> {
+pub fn build_home_page(
+    providers: Vec<ProviderChoice>,
+    profile: &'static OutputProfile,
+) -> anyhow::Result<String> {
+    let default_provider = providers
+        .first()
+        .map(|p| p.name.clone())
+        .unwrap_or_default();
+
     let template = templ! {
         <html>
         <head>
@@ -53,7 +78,7 @@ pub fn build_home_page(serpapi_left: u64) -> anyhow::Result<String> {
 
             <br/>
             <br/>
-            <center><img src="/static/logo.gif" alt="BoingSearch Logo"/></center>
+            <center>#render_logo(profile);</center>
 
             <center>
                 <h2>The Search Engine for Amigans and Friends</h2>
@@ -64,14 +89,18 @@ pub fn build_home_page(serpapi_left: u64) -> anyhow::Result<String> {
                 <form action="/" method="get">
                 I am looking for: <br/>
                     <input type="text" size="30" name="q"/> <br/>
-                    #if serpapi_left > 0 {
-                        <input type="checkbox" name="premium" /> Use SerpAPI(limited count per month) for search <br/>
-                        Left {serpapi_left} search queries on SerpAPI account
-                        <small>SerpAPI uses Google as source, in other case - we are using DuckDuckGo</small> <br/>
+                    #if providers.len() > 1 {
+                        Search with: #render_provider_select(providers.clone(), default_provider.clone()); <br/>
+                    }
+                    #for provider in &providers {
+                        #if let Some(left) = provider.searches_left {
+                            <small>Left {left} search queries on {provider.title} account</small> <br/>
+                        }
                     }
 
                     <input type="submit" value="Search!"/>
                 </form>
+                <small>Browser profile: {profile.title} (<a href="/profile/">change</a>)</small>
             </center>
 
             #build_footer();
@@ -79,10 +108,13 @@ pub fn build_home_page(serpapi_left: u64) -> anyhow::Result<String> {
         </html>
     };
 
-    template.render(&())
+    apply_profile(&template.render(&())?, profile)
 }
 
-pub fn build_error_page(message: String) -> anyhow::Result<String> {
+pub fn build_error_page(
+    message: String,
+    profile: &'static OutputProfile,
+) -> anyhow::Result<String> {
     let template = templ! {
         <html>
         <head>
@@ -92,7 +124,7 @@ pub fn build_error_page(message: String) -> anyhow::Result<String> {
 
             <br/>
             <br/>
-            <center><a href="/"><img src="/static/logo.gif" alt="BoingSearch Logo"/></a></center>
+            <center><a href="/">#render_logo(profile);</a></center>
             <br/>
             <center><h2>Warning</h2></center>
 
@@ -103,22 +135,97 @@ pub fn build_error_page(message: String) -> anyhow::Result<String> {
         </html>
     };
 
-    Ok(template.render(&())?.to_string())
+    apply_profile(&template.render(&())?, profile)
 }
 
 fn render_serp_item(serp_item: Serp) -> templ_ret!['static] {
     templ! {
         <h3>{serp_item.title}</h3>
         <h4>{serp_item.displayed_link}</h4>
-        <a href={format!("/browse/?url={}", serp_item.link.clone())}>[Simplified page]</a> |
+        <a href={format!("/browse/?url={}", urlencoding::encode(&serp_item.link))}>[Simplified page]</a> |
         <a href={serp_item.link}>[Full version]</a><br/>
         <small>
             {deunicode(&serp_item.snippet.clone().unwrap_or("".to_string()))}
         </small>
+        #if !serp_item.engines.is_empty() {
+            <br/>
+            <small><i>Found by: {serp_item.engines.join(", ")}</i></small>
+        }
         <hr/>
     }
 }
 
+fn render_logo(profile: &'static OutputProfile) -> templ_ret!['static] {
+    templ! {
+        #if profile.images.enabled {
+            <img src="/static/logo.gif" alt="BoingSearch Logo"/>
+        } else {
+            <b>BoingSearch!</b>
+        }
+    }
+}
+
+fn render_provider_select(providers: Vec<ProviderChoice>, selected: String) -> templ_ret!['static] {
+    templ! {
+        <select name="provider">
+            #for provider in &providers {
+                #if provider.name == selected {
+                    <option value={provider.name} selected>{provider.title}</option>
+                } else {
+                    <option value={provider.name}>{provider.title}</option>
+                }
+            }
+        </select>
+    }
+}
+
+/// Link to other page of results, `params` like `profile` and `charset` are kept
+fn page_link(query: &str, provider: &str, offset: u32, params: &[(String, String)]) -> String {
+    let mut link = format!("/?q={}", urlencoding::encode(query));
+
+    if !provider.is_empty() {
+        link.push_str(&format!("&provider={}", urlencoding::encode(provider)));
+    }
+
+    link.push_str(&params_query(params));
+
+    if offset > 0 {
+        link.push_str(&format!("&offset={offset}"));
+    }
+
+    link
+}
+
+fn render_pagination(
+    query: String,
+    serp_result: SearchResponse,
+    params: Vec<(String, String)>,
+) -> templ_ret!['static] {
+    let provider = serp_result.provider.as_str();
+    // Providers have pages of different size, so they tell where previous page starts
+    let prev_link = serp_result
+        .prev_offset
+        .map(|offset| page_link(&query, provider, offset, &params));
+    let next_link = serp_result
+        .next_offset
+        .filter(|_| !serp_result.serp.is_empty())
+        .map(|offset| page_link(&query, provider, offset, &params));
+
+    templ! {
+        <center>
+            #if let Some(link) = &prev_link {
+                <a href={link}>{"[< Previous page]"}</a>
+            }
+            #if prev_link.is_some() && next_link.is_some() {
+                |
+            }
+            #if let Some(link) = &next_link {
+                <a href={link}>{"[Next page >]"}</a>
+            }
+        </center>
+    }
+}
+
 fn build_footer() -> templ_ret!['static] {
     templ! {
             <br/>
@@ -130,3 +237,56 @@ fn build_footer() -> templ_ret!['static] {
             <center>Powered by SerpAPI, DuckDuckGo and some magic</center>
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::profile::find_profile;
+    use std::collections::HashMap;
+
+    fn response() -> SearchResponse {
+        SearchResponse {
+            serp: vec![Serp {
+                link: "http://aminet.net/".to_string(),
+                displayed_link: "aminet.net".to_string(),
+                title: "Aminet".to_string(),
+                snippet: Some("Amiga software archive".to_string()),
+                engines: vec!["wiby".to_string()],
+            }],
+            inputs: HashMap::new(),
+            offset: 0,
+            next_offset: Some(10),
+            prev_offset: None,
+            provider: "duckduckgo".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_serp_page_follows_profile() -> anyhow::Result<()> {
+        let page = |name| {
+            serp_result_page(
+                "amiga".to_string(),
+                response(),
+                vec![],
+                find_profile(name).unwrap(),
+                vec![("profile".to_string(), name.to_string())],
+            )
+        };
+
+        let generic = page("default")?;
+        assert!(generic.contains("<small>"));
+        assert!(generic.contains("<center>"));
+
+        for name in ["mosaic", "lynx"] {
+            let page = page(name)?;
+            assert!(!page.contains("<small>"));
+            assert!(!page.contains("<center>"));
+            assert!(page.contains("Amiga software archive"));
+            assert!(page.contains("<input"));
+            assert!(page.contains("Next page"));
+            assert!(page.contains(&format!("&amp;profile={name}&amp;offset=10")));
+        }
+
+        Ok(())
+    }
+}
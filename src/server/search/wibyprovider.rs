@@ -0,0 +1,152 @@
+use crate::server::search::{
+    SearchProvider, SearchResponse, Serp, clean_text, fetch_serp_page, prev_offset,
+};
+use kuchiki::NodeRef;
+use kuchiki::parse_html;
+use kuchiki::traits::*;
+use log::warn;
+use std::collections::HashMap;
+use url::Url;
+
+/// Wiby shows fixed amount of results per page
+const WIBY_PAGE_SIZE: u32 = 12;
+
+#[derive(Clone, Debug)]
+pub struct WibyProvider {
+    pub base_url: Url,
+}
+
+impl WibyProvider {
+    pub fn new(base_url: Url) -> Self {
+        Self { base_url }
+    }
+
+    fn search_url(&self, query: &str, offset: u32) -> anyhow::Result<Url> {
+        // Relative join keeps path of mirrors that live in subdirectory
+        let mut url = self.base_url.join("./")?;
+        url.query_pairs_mut().append_pair("q", query);
+
+        if offset >= WIBY_PAGE_SIZE {
+            url.query_pairs_mut()
+                .append_pair("p", &(offset / WIBY_PAGE_SIZE + 1).to_string());
+        }
+
+        Ok(url)
+    }
+
+    fn try_extract_serp(block: &NodeRef) -> anyhow::Result<Serp> {
+        let link = block
+            .select_first("a.tlink")
+            .map_err(|_| anyhow::anyhow!("No link present"))?;
+        let link_attrs = link.attributes.borrow();
+        let href = link_attrs
+            .get("href")
+            .ok_or(anyhow::anyhow!("No href in link"))?;
+
+        let displayed_link = block
+            .select_first("p.url")
+            .map(|u| clean_text(&u.text_contents()))
+            .unwrap_or(href.to_string());
+
+        // Snippet is first paragraph without class
+        let snippet = block
+            .select("p")
+            .map_err(|_| anyhow::anyhow!("Cannot select paragraphs"))?
+            .find(|p| p.attributes.borrow().get("class").is_none())
+            .map(|p| clean_text(&p.text_contents()))
+            .filter(|s| !s.is_empty());
+
+        Ok(Serp {
+            link: href.to_string(),
+            displayed_link: deunicode::deunicode(&displayed_link),
+            title: deunicode::deunicode(&clean_text(&link.text_contents())),
+            snippet,
+            engines: Vec::new(),
+        })
+    }
+
+    fn parse_serp_result(page_txt: String, offset: u32) -> anyhow::Result<SearchResponse> {
+        let page = parse_html().one(page_txt);
+        let mut serp_items = Vec::new();
+
+        let blocks = page
+            .select("blockquote")
+            .map_err(|_| anyhow::anyhow!("Cannot select result blocks"))?;
+
+        for block in blocks {
+            match Self::try_extract_serp(block.as_node()) {
+                Ok(serp) => serp_items.push(serp),
+                Err(e) => warn!("Error happens: {e:?}"),
+            }
+        }
+
+        let has_more = page.select_first("a.more").is_ok();
+
+        Ok(SearchResponse {
+            serp: serp_items,
+            inputs: HashMap::new(),
+            offset,
+            next_offset: has_more.then_some(offset + WIBY_PAGE_SIZE),
+            prev_offset: prev_offset(offset, WIBY_PAGE_SIZE),
+            provider: String::new(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl SearchProvider for WibyProvider {
+    async fn make_serp_request(
+        &self,
+        query: String,
+        offset: u32,
+    ) -> anyhow::Result<SearchResponse> {
+        let page_txt = fetch_serp_page(self.search_url(&query, offset)?).await?;
+
+        Self::parse_serp_result(page_txt, offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_wiby_fixture() -> anyhow::Result<()> {
+        let page = include_str!("../../../tests/fixtures/wiby.html").to_string();
+        let result = WibyProvider::parse_serp_result(page, 0)?;
+
+        assert_eq!(result.serp.len(), 3);
+        assert_eq!(result.serp[0].link, "http://www.amiga-hardware.com/");
+        assert_eq!(result.serp[0].title, "Amiga Hardware Database");
+        assert_eq!(
+            result.serp[0].snippet.as_deref(),
+            Some("Detailed info and pictures of Amiga hardware.")
+        );
+        assert_eq!(result.serp[2].snippet, None);
+        assert_eq!(result.next_offset, Some(WIBY_PAGE_SIZE));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wiby_search_url() -> anyhow::Result<()> {
+        let provider = WibyProvider::new(Url::parse("https://wiby.me/")?);
+
+        assert_eq!(
+            provider.search_url("amiga 500", 0)?.as_str(),
+            "https://wiby.me/?q=amiga+500"
+        );
+        assert_eq!(
+            provider.search_url("amiga", 24)?.as_str(),
+            "https://wiby.me/?q=amiga&p=3"
+        );
+
+        let mirror = WibyProvider::new(Url::parse("https://example.com/wiby/")?);
+        assert_eq!(
+            mirror.search_url("amiga", 0)?.as_str(),
+            "https://example.com/wiby/?q=amiga"
+        );
+
+        Ok(())
+    }
+}
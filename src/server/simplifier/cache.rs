@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use log::debug;
+use reqwest::header::{CACHE_CONTROL, DATE, EXPIRES, HeaderMap};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// `[page_cache]` section of config.toml
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PageCacheConfig {
+    /// Used when origin doesn't say how long page is fresh, 0 disables caching
+    #[serde(default = "default_ttl_secs")]
+    pub default_ttl_secs: i64,
+    /// Origin can't keep page in cache longer than this
+    #[serde(default = "default_max_ttl_secs")]
+    pub max_ttl_secs: i64,
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+    /// Total size of cached bodies
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: usize,
+}
+
+fn default_ttl_secs() -> i64 {
+    300
+}
+
+fn default_max_ttl_secs() -> i64 {
+    3600
+}
+
+fn default_max_entries() -> usize {
+    500
+}
+
+fn default_max_bytes() -> usize {
+    32 * 1024 * 1024
+}
+
+impl Default for PageCacheConfig {
+    fn default() -> Self {
+        Self {
+            default_ttl_secs: default_ttl_secs(),
+            max_ttl_secs: default_max_ttl_secs(),
+            max_entries: default_max_entries(),
+            max_bytes: default_max_bytes(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct PageCacheKey {
+    pub url: String,
+    /// Raw body is shared by all profiles, simplified output is not. Image layout is part of it
+    pub profile: Option<String>,
+}
+
+impl PageCacheKey {
+    pub fn raw(url: &Url) -> Self {
+        Self {
+            url: url.to_string(),
+            profile: None,
+        }
+    }
+
+    pub fn simplified(url: &Url, profile: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            profile: Some(profile.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CachedPage {
+    /// Url after redirects, relative links are resolved against it
+    pub final_url: Url,
+    pub body: String,
+    /// Unix timestamp
+    pub expires_at: i64,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    stored_at: i64,
+    page: CachedPage,
+}
+
+/// Fetched pages and their simplified versions
+#[derive(Debug)]
+pub struct PageCache {
+    config: PageCacheConfig,
+    entries: Mutex<HashMap<PageCacheKey, CacheEntry>>,
+}
+
+impl PageCache {
+    pub fn new(config: PageCacheConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.config.default_ttl_secs > 0 && self.config.max_entries > 0
+    }
+
+    /// When page fetched now should expire, None if origin doesn't allow storing it
+    pub fn expires_at(&self, headers: &HeaderMap) -> Option<i64> {
+        self.expiry(headers, Utc::now().timestamp())
+    }
+
+    fn expiry(&self, headers: &HeaderMap, now: i64) -> Option<i64> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let ttl = origin_ttl(headers, now)?.unwrap_or(self.config.default_ttl_secs);
+        let ttl = ttl.min(self.config.max_ttl_secs);
+
+        (ttl > 0).then_some(now + ttl)
+    }
+
+    pub fn get(&self, key: &PageCacheKey) -> Option<CachedPage> {
+        let entries = self.entries.lock().ok()?;
+        let entry = entries.get(key)?;
+
+        if entry.page.expires_at <= Utc::now().timestamp() {
+            return None;
+        }
+
+        debug!("Page cache hit: {key:?}");
+
+        Some(entry.page.clone())
+    }
+
+    pub fn insert(&self, key: PageCacheKey, page: CachedPage) {
+        if !self.is_enabled() || page.body.len() > self.config.max_bytes {
+            return;
+        }
+
+        let now = Utc::now().timestamp();
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+
+        entries.remove(&key);
+        entries.retain(|_, entry| entry.page.expires_at > now);
+
+        let mut total_bytes = entries.values().map(|e| e.page.body.len()).sum::<usize>();
+
+        while entries.len() >= self.config.max_entries
+            || total_bytes + page.body.len() > self.config.max_bytes
+        {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.stored_at)
+                .map(|(key, _)| key.clone());
+
+            match oldest.and_then(|oldest| entries.remove(&oldest)) {
+                Some(removed) => total_bytes -= removed.page.body.len(),
+                None => break,
+            }
+        }
+
+        entries.insert(
+            key,
+            CacheEntry {
+                stored_at: now,
+                page,
+            },
+        );
+    }
+
+    /// Drops every version of page, or whole cache if url isn't set. Returns number of dropped
+    /// entries
+    pub fn purge(&self, url: Option<&Url>) -> usize {
+        let Ok(mut entries) = self.entries.lock() else {
+            return 0;
+        };
+        let before = entries.len();
+
+        match url {
+            Some(url) => entries.retain(|key, _| key.url != url.as_str()),
+            None => entries.clear(),
+        }
+
+        before - entries.len()
+    }
+}
+
+/// Freshness lifetime from Cache-Control or Expires. Outer None means page must not be stored,
+/// inner one - origin said nothing
+fn origin_ttl(headers: &HeaderMap, now: i64) -> Option<Option<i64>> {
+    let directives = headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .map(|d| d.trim().to_ascii_lowercase())
+        .collect::<Vec<_>>();
+
+    // Pages for single user can't be shared between visitors
+    if directives
+        .iter()
+        .any(|d| d == "no-store" || d == "no-cache" || d == "private")
+    {
+        return None;
+    }
+
+    let max_age = |name: &str| {
+        directives
+            .iter()
+            .filter_map(|d| d.split_once('='))
+            .find(|(k, _)| k.trim() == name)
+            .and_then(|(_, v)| v.trim().trim_matches('"').parse::<i64>().ok())
+    };
+
+    if let Some(ttl) = max_age("s-maxage").or_else(|| max_age("max-age")) {
+        return Some(Some(ttl));
+    }
+
+    let http_date = |name| {
+        headers
+            .get(name)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| DateTime::parse_from_rfc2822(h).ok())
+            .map(|d| d.timestamp())
+    };
+
+    match headers.get(EXPIRES) {
+        // Invalid Expires like "0" means already expired
+        Some(_) => {
+            let expires = http_date(EXPIRES)?;
+            let date = http_date(DATE).unwrap_or(now);
+
+            Some(Some(expires - date))
+        }
+        None => Some(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.parse().unwrap(), v.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_origin_freshness() {
+        let cache = PageCache::new(PageCacheConfig::default());
+        let now = 1_000_000;
+
+        assert_eq!(cache.expiry(&headers(&[]), now), Some(now + 300));
+        assert_eq!(
+            cache.expiry(&headers(&[("cache-control", "public, max-age=60")]), now),
+            Some(now + 60)
+        );
+        assert_eq!(
+            cache.expiry(&headers(&[("cache-control", "max-age=86400")]), now),
+            Some(now + 3600)
+        );
+        assert_eq!(
+            cache.expiry(&headers(&[("cache-control", "no-store")]), now),
+            None
+        );
+        assert_eq!(
+            cache.expiry(
+                &headers(&[
+                    ("date", "Sun, 18 Oct 2026 10:00:00 GMT"),
+                    ("expires", "Sun, 18 Oct 2026 10:02:00 GMT"),
+                ]),
+                now
+            ),
+            Some(now + 120)
+        );
+        assert_eq!(cache.expiry(&headers(&[("expires", "0")]), now), None);
+    }
+
+    #[test]
+    fn test_size_limits_and_purge() {
+        let cache = PageCache::new(PageCacheConfig {
+            max_bytes: 10,
+            ..PageCacheConfig::default()
+        });
+        let url = Url::parse("http://aminet.net/").unwrap();
+        let page = |body: &str| CachedPage {
+            final_url: url.clone(),
+            body: body.to_string(),
+            expires_at: Utc::now().timestamp() + 60,
+        };
+
+        cache.insert(PageCacheKey::raw(&url), page("raw"));
+        cache.insert(PageCacheKey::simplified(&url, "aweb"), page("<p>"));
+        assert!(cache.get(&PageCacheKey::raw(&url)).is_some());
+
+        // Doesn't fit together with both of them, so older ones go away
+        cache.insert(PageCacheKey::simplified(&url, "lynx"), page("<p>a</p>"));
+        assert!(cache.get(&PageCacheKey::simplified(&url, "lynx")).is_some());
+        assert_eq!(cache.entries.lock().unwrap().len(), 1);
+
+        cache.insert(PageCacheKey::raw(&url), page("too long body"));
+        assert!(cache.get(&PageCacheKey::raw(&url)).is_none());
+
+        assert_eq!(cache.purge(Some(&url)), 1);
+        assert!(cache.get(&PageCacheKey::simplified(&url, "lynx")).is_none());
+    }
+}
@@ -0,0 +1,303 @@
+use ammonia::Builder;
+use kuchiki::parse_html;
+use kuchiki::traits::*;
+use kuchiki::{Attribute, ElementData, ExpandedName, NodeRef};
+use url::Url;
+
+use crate::server::charset::OutputCharset;
+use crate::server::profile::OutputProfile;
+use crate::server::simplifier::transform::serialize_body;
+
+/// Hidden field that keeps real form target
+pub const FORM_ACTION_FIELD: &str = "boing_action";
+
+/// Hidden fields `boing_profile` and `boing_charset` keep parameters of the page, GET form
+/// replaces query of its action
+const FORM_PARAM_PREFIX: &str = "boing_";
+const FORM_PARAMS: [&str; 2] = ["profile", "charset"];
+
+/// Endpoint that forwards submitted forms to origin
+pub const FORM_RELAY_PATH: &str = "/browse/form";
+
+pub(crate) const FORM_TAGS: &[&str] = &[
+    "form", "input", "select", "option", "textarea", "label", "button", "fieldset", "legend",
+];
+
+const FORM_ATTRIBUTES: &[(&str, &[&str])] = &[
+    ("form", &["action", "method", "enctype"]),
+    (
+        "input",
+        &["type", "name", "value", "size", "maxlength", "checked"],
+    ),
+    ("select", &["name", "size", "multiple"]),
+    ("option", &["value", "selected"]),
+    ("textarea", &["name", "rows", "cols"]),
+    ("button", &["type", "name", "value"]),
+];
+
+/// Forms of original page, cleaned the same way as content. Readability drops most of them,
+/// search boxes and login forms usually live outside of article
+pub fn extract_forms(input: &str, profile: &OutputProfile) -> anyhow::Result<String> {
+    let document = parse_html().one(input);
+
+    let mut tags = profile.tags.clone();
+    tags.extend(FORM_TAGS);
+
+    let mut builder = Builder::new();
+    builder.tags(tags).link_rel(None);
+    for (tag, attributes) in FORM_ATTRIBUTES {
+        builder.add_tag_attributes(*tag, attributes.iter());
+    }
+
+    let mut result = String::new();
+
+    for form in document
+        .select("form")
+        .map_err(|_| anyhow::anyhow!("Cannot select forms"))?
+    {
+        // Nested forms are invalid, parser already moved them out
+        let mut bytes = vec![];
+        form.as_node()
+            .serialize(&mut bytes)
+            .map_err(|e| anyhow::anyhow!(format!("Cannot serialize form: {e}")))?;
+
+        result.push_str(&builder.clean(&String::from_utf8(bytes)?).to_string());
+    }
+
+    Ok(result)
+}
+
+/// Points forms to relay endpoint. Forms with file uploads can't be relayed, so they are removed
+pub fn rewrite_forms(document: &NodeRef, page_url: &Url) -> anyhow::Result<()> {
+    let forms = document
+        .select("form")
+        .map_err(|_| anyhow::anyhow!("Cannot select forms"))?
+        .collect::<Vec<_>>();
+
+    for form in forms {
+        let has_files = form
+            .as_node()
+            .select("input[type=file i]")
+            .map_err(|_| anyhow::anyhow!("Cannot select inputs"))?
+            .next()
+            .is_some();
+
+        let (action, method, multipart) = {
+            let attributes = form.attributes.borrow();
+            let action = attributes.get("action").unwrap_or_default().trim();
+            let method = attributes
+                .get("method")
+                .unwrap_or("get")
+                .to_ascii_lowercase();
+            let multipart = attributes
+                .get("enctype")
+                .is_some_and(|e| e.eq_ignore_ascii_case("multipart/form-data"));
+
+            (page_url.join(action), method, multipart)
+        };
+
+        let Ok(action) = action else {
+            form.as_node().detach();
+            continue;
+        };
+
+        if has_files || multipart || !matches!(action.scheme(), "http" | "https") {
+            form.as_node().detach();
+            continue;
+        }
+
+        {
+            let mut attributes = form.attributes.borrow_mut();
+            attributes.remove("enctype");
+            attributes.insert("action", FORM_RELAY_PATH.to_string());
+            attributes.insert(
+                "method",
+                if method == "post" { "post" } else { "get" }.to_string(),
+            );
+        }
+
+        form.as_node()
+            .prepend(hidden_input(&form, FORM_ACTION_FIELD, action.as_str()));
+    }
+
+    Ok(())
+}
+
+fn hidden_input(form: &ElementData, name: &str, value: &str) -> NodeRef {
+    let mut qual_name = form.name.clone();
+    qual_name.local = "input".into();
+    let attributes = [("type", "hidden"), ("name", name), ("value", value)].map(|(key, value)| {
+        (
+            ExpandedName::new("", key),
+            Attribute {
+                prefix: None,
+                value: value.to_string(),
+            },
+        )
+    });
+
+    NodeRef::new_element(qual_name, attributes)
+}
+
+/// Puts `profile` and `charset` of current request into relayed forms of simplified page
+pub fn add_form_params(html: &str, params: &[(String, String)]) -> anyhow::Result<String> {
+    if params.is_empty() || !html.contains(FORM_RELAY_PATH) {
+        return Ok(html.to_string());
+    }
+
+    let document = parse_html().one(html);
+    for form in document
+        .select("form")
+        .map_err(|_| anyhow::anyhow!("Cannot select forms"))?
+    {
+        if form.attributes.borrow().get("action") != Some(FORM_RELAY_PATH) {
+            continue;
+        }
+
+        for (key, value) in params
+            .iter()
+            .filter(|(k, _)| FORM_PARAMS.contains(&k.as_str()))
+        {
+            form.as_node().append(hidden_input(
+                &form,
+                &format!("{FORM_PARAM_PREFIX}{key}"),
+                value,
+            ));
+        }
+    }
+
+    serialize_body(&document)
+}
+
+/// `profile` and `charset` that were put into relayed form
+pub fn form_params(data: &[u8]) -> Vec<(String, String)> {
+    parse_form_data(data, OutputCharset::Utf8)
+        .into_iter()
+        .filter_map(|(name, value)| {
+            let key = name.strip_prefix(FORM_PARAM_PREFIX)?;
+            FORM_PARAMS.contains(&key).then(|| (key.to_string(), value))
+        })
+        .collect()
+}
+
+/// Submitted form in `application/x-www-form-urlencoded`. Old browsers send it in charset of
+/// the page, so values are decoded with it
+pub fn parse_form_data(data: &[u8], charset: OutputCharset) -> Vec<(String, String)> {
+    let decode = |part: &[u8]| {
+        let part = part
+            .iter()
+            .map(|b| if *b == b'+' { b' ' } else { *b })
+            .collect::<Vec<_>>();
+
+        charset.decode(&urlencoding::decode_binary(&part))
+    };
+
+    data.split(|b| *b == b'&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.iter().position(|b| *b == b'=') {
+            Some(pos) => (decode(&pair[..pos]), decode(&pair[pos + 1..])),
+            None => (decode(pair), String::new()),
+        })
+        .collect()
+}
+
+/// Splits relayed form into origin url and fields that should be sent there
+pub fn take_action(
+    mut fields: Vec<(String, String)>,
+) -> anyhow::Result<(Url, Vec<(String, String)>)> {
+    let position = fields
+        .iter()
+        .position(|(name, _)| name == FORM_ACTION_FIELD)
+        .ok_or(anyhow::anyhow!("Form has no target"))?;
+    let (_, action) = fields.remove(position);
+    fields.retain(|(name, _)| {
+        name.strip_prefix(FORM_PARAM_PREFIX)
+            .is_none_or(|key| !FORM_PARAMS.contains(&key))
+    });
+
+    Ok((Url::parse(&action)?, fields))
+}
+
+/// Url that GET form would open, its data replaces query of action
+pub fn get_form_url(mut action: Url, fields: &[(String, String)]) -> Url {
+    action.set_fragment(None);
+    action
+        .query_pairs_mut()
+        .clear()
+        .extend_pairs(fields.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+
+    if fields.is_empty() {
+        action.set_query(None);
+    }
+
+    action
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::profile::default_profile;
+
+    #[test]
+    fn test_forms_are_relayed() {
+        let page = r#"<html><body>
+            <form action="/w/index.php" class="search"><input name="search" size="20" style="x"><input type="submit" value="Go"></form>
+            <form method="POST" action="https://example.com/upload" enctype="multipart/form-data"><input type="file" name="f"></form>
+            <script>alert(1)</script>
+        </body></html>"#;
+
+        let forms = extract_forms(page, default_profile()).unwrap();
+        assert!(!forms.contains("style"));
+        assert!(!forms.contains("script"));
+
+        let document = parse_html().one(forms.as_str());
+        rewrite_forms(
+            &document,
+            &Url::parse("https://en.wikipedia.org/wiki/Amiga").unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            serialize_body(&document).unwrap(),
+            concat!(
+                r#"<form action="/browse/form" method="get">"#,
+                r#"<input name="boing_action" type="hidden" value="https://en.wikipedia.org/w/index.php">"#,
+                r#"<input name="search" size="20"><input type="submit" value="Go"></form>"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_relayed_form_data() {
+        let fields = parse_form_data(
+            b"boing_action=https%3A%2F%2Fen.wikipedia.org%2Fw%2Findex.php%3Fold%3D1&search=Caf%E9+Amiga&go",
+            OutputCharset::Latin1,
+        );
+        let (action, fields) = take_action(fields).unwrap();
+
+        assert_eq!(
+            get_form_url(action, &fields).as_str(),
+            "https://en.wikipedia.org/w/index.php?search=Caf%C3%A9+Amiga&go="
+        );
+        assert!(take_action(vec![]).is_err());
+    }
+
+    #[test]
+    fn test_form_params_survive_relay() {
+        let page = r#"<form action="/browse/form" method="get"><input name="q"></form>"#;
+        let params = vec![
+            ("profile".to_string(), "mosaic".to_string()),
+            ("charset".to_string(), "latin1".to_string()),
+        ];
+
+        let page = add_form_params(page, &params).unwrap();
+        assert!(page.contains(r#"<input name="boing_profile" type="hidden" value="mosaic">"#));
+        assert!(page.contains(r#"<input name="boing_charset" type="hidden" value="latin1">"#));
+
+        let data = b"boing_action=https%3A%2F%2Fexample.com%2F&q=amiga&boing_profile=mosaic&boing_charset=latin1";
+        assert_eq!(form_params(data), params);
+
+        let (_, fields) = take_action(parse_form_data(data, OutputCharset::Utf8)).unwrap();
+        assert_eq!(fields, vec![("q".to_string(), "amiga".to_string())]);
+    }
+}
@@ -0,0 +1,452 @@
+pub mod cache;
+pub mod forms;
+pub mod paging;
+pub mod transform;
+
+use ammonia::Builder;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use templr::Trust;
+use templr::{Template, templ, templ_ret};
+use url::Url;
+
+use crate::server::charset::decode_body;
+use crate::server::clients::FetchClient;
+use crate::server::fetch;
+use crate::server::profile::OutputProfile;
+use crate::server::simplifier::cache::{CachedPage, PageCache, PageCacheKey};
+use crate::server::simplifier::transform::{PageTransform, apply_profile};
+
+/// `[simplifier]` section of config.toml
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SimplifierConfig {
+    /// Approximate size of single page of simplified output in bytes, 0 disables splitting
+    #[serde(default = "default_page_size")]
+    pub page_size: usize,
+    /// Keep forms of pages, they are submitted through simplifier
+    #[serde(default)]
+    pub keep_forms: bool,
+}
+
+fn default_page_size() -> usize {
+    24 * 1024
+}
+
+impl Default for SimplifierConfig {
+    fn default() -> Self {
+        Self {
+            page_size: default_page_size(),
+            keep_forms: false,
+        }
+    }
+}
+
+/// Position of shown part in long page
+#[derive(Debug, Clone)]
+pub struct PageNav {
+    /// Starts from 1
+    pub current: usize,
+    pub total: usize,
+    /// Whole page is shown, so links switch back to paged view
+    pub showing_all: bool,
+    /// Simplifier endpoint that links lead to
+    pub browse_path: String,
+    /// Parameters of current request that links keep, like `profile` and `charset`
+    pub params: Vec<(String, String)>,
+}
+
+impl PageNav {
+    fn link(&self, path: &str, query: &str) -> String {
+        format!(
+            "{}?url={}{}&{query}",
+            self.browse_path,
+            urlencoding::encode(path),
+            params_query(&self.params)
+        )
+    }
+}
+
+/// Kept parameters as a tail of query, every one starts with `&`
+pub fn params_query(params: &[(String, String)]) -> String {
+    params
+        .iter()
+        .map(|(k, v)| format!("&{k}={}", urlencoding::encode(v)))
+        .collect()
+}
+
+pub fn simplify_html(
+    input: String,
+    base: String,
+    profile: &OutputProfile,
+    keep_forms: bool,
+) -> anyhow::Result<String> {
+    let forms = match keep_forms {
+        true => forms::extract_forms(&input, profile)?,
+        false => String::new(),
+    };
+
+    let mut readability = readable_readability::Readability::new();
+    readability.base_url(Url::from_str(&base)?);
+    readability.clean_attributes(true);
+    readability.strip_unlikelys(true);
+    readability.clean_conditionally(false);
+    readability.weight_classes(false);
+
+    let (content, meta) = readability.parse(&input);
+
+    let mut content_bytes = vec![];
+    content
+        .serialize(&mut content_bytes)
+        .map_err(|e| anyhow::anyhow!(format!("Cannot serialize content: {e}")))?;
+    let content = std::str::from_utf8(&content_bytes)
+        .map_err(|e| anyhow::anyhow!(format!("Can't make string from content. Error: {e}")))?;
+
+    let result = Builder::new()
+        .tags(profile.tags.clone())
+        .link_rel(None)
+        .clean(content)
+        .to_string();
+
+    let mut result = format!(
+        "<h1>{}</h1>{}",
+        meta.page_title.unwrap_or("Untitled page".to_string()),
+        result
+    );
+
+    if !forms.is_empty() {
+        result.push_str("<hr><h3>Forms on this page</h3>");
+        result.push_str(&forms);
+    }
+
+    Ok(result)
+}
+
+/// Checks that response is a page and decodes it
+async fn read_page(response: reqwest::Response, client: &FetchClient) -> anyhow::Result<String> {
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .map(str::to_string);
+    fetch::ensure_html(content_type.as_deref())?;
+
+    let limits = &client.limits;
+    let bytes = fetch::read_body(response, limits.max_page_bytes, limits.read_timeout()).await?;
+
+    Ok(decode_body(&bytes, content_type.as_deref()))
+}
+
+/// Downloads page, cached copy is used while it is fresh
+async fn fetch_page(
+    url: Url,
+    cache: &PageCache,
+    client: &FetchClient,
+) -> anyhow::Result<CachedPage> {
+    let key = PageCacheKey::raw(&url);
+    if !client.is_private(&url)
+        && let Some(page) = cache.get(&key)
+    {
+        return Ok(page);
+    }
+
+    let response = client.get(url).await?;
+
+    // Relative links should be resolved against url after redirects
+    let final_url = response.url().clone();
+    // Jar already has cookies that were set by this response, and error pages shouldn't
+    // stay in cache after site recovers
+    let expires_at = match client.is_private(&final_url) || !response.status().is_success() {
+        true => None,
+        false => cache.expires_at(response.headers()),
+    };
+    let body = read_page(response, client).await?;
+
+    let page = CachedPage {
+        final_url,
+        body,
+        expires_at: expires_at.unwrap_or_default(),
+    };
+
+    if expires_at.is_some() {
+        cache.insert(key, page.clone());
+    }
+
+    Ok(page)
+}
+
+fn render_page(
+    page_url: Url,
+    body: String,
+    base_path: String,
+    profile: &OutputProfile,
+    keep_forms: bool,
+) -> anyhow::Result<String> {
+    let simplified = simplify_html(body, page_url.to_string(), profile, keep_forms)?;
+
+    PageTransform::new(page_url, base_path, profile).apply(&simplified)
+}
+
+pub async fn process_page(
+    page: String,
+    base_path: String,
+    profile: &OutputProfile,
+    cache: &PageCache,
+    client: &FetchClient,
+    keep_forms: bool,
+) -> anyhow::Result<String> {
+    let url = Url::from_str(&page)?;
+
+    let key = PageCacheKey::simplified(
+        &url,
+        &format!("{}/{}", profile.name, profile.images.layout_key()),
+    );
+    if !client.is_private(&url)
+        && let Some(page) = cache.get(&key)
+    {
+        return Ok(page.body);
+    }
+
+    let fetched = fetch_page(url, cache, client).await?;
+    let body = render_page(
+        fetched.final_url.clone(),
+        fetched.body,
+        base_path,
+        profile,
+        keep_forms,
+    )?;
+
+    // Simplified version lives as long as the page it was made from
+    if fetched.expires_at > 0 {
+        cache.insert(
+            key,
+            CachedPage {
+                final_url: fetched.final_url,
+                body: body.clone(),
+                expires_at: fetched.expires_at,
+            },
+        );
+    }
+
+    Ok(body)
+}
+
+/// Sends relayed POST form to origin and simplifies the answer, such pages are never cached
+pub async fn process_form_post(
+    action: Url,
+    fields: Vec<(String, String)>,
+    base_path: String,
+    profile: &OutputProfile,
+    client: &FetchClient,
+    keep_forms: bool,
+) -> anyhow::Result<String> {
+    let response = client.post_form(action, &fields).await?;
+    let final_url = response.url().clone();
+    let body = read_page(response, client).await?;
+
+    render_page(final_url, body, base_path, profile, keep_forms)
+}
+
+fn render_page_nav(path: String, nav: PageNav) -> templ_ret!['static] {
+    let prev_link = (!nav.showing_all && nav.current > 1)
+        .then(|| nav.link(&path, &format!("page={}", nav.current - 1)));
+    let next_link = (!nav.showing_all && nav.current < nav.total)
+        .then(|| nav.link(&path, &format!("page={}", nav.current + 1)));
+    let all_link = nav.link(&path, "all=1");
+    let paged_link = nav.link(&path, "page=1");
+
+    templ! {
+        <center>
+            #if nav.showing_all {
+                <a href={paged_link}>{"[Split into pages]"}</a>
+            } else {
+                #if let Some(link) = &prev_link {
+                    <a href={link}>{"[< Previous page]"}</a>
+                }
+                Page {nav.current} of {nav.total}
+                #if let Some(link) = &next_link {
+                    <a href={link}>{"[Next page >]"}</a>
+                }
+                | <a href={all_link}>{"[Show everything]"}</a>
+            }
+        </center>
+    }
+}
+
+pub fn proxy_page(
+    path: String,
+    content: String,
+    nav: Option<PageNav>,
+    profile: &OutputProfile,
+    params: &[(String, String)],
+) -> anyhow::Result<String> {
+    let content = forms::add_form_params(&content, params)?;
+    let template = templ! {
+        <html>
+            <head>
+                <title>BoingSearch Simplifier</title>
+            </head>
+            <body>
+            <form action="/browse/" method="get">
+                <a href="/">Back to the root!</a> | Current URL:
+                <input type="text" size="30" name="url" value={path}/> <input type="submit" value="Go!"/>
+                #if !path.is_empty() {
+                    <br/>
+                    <a href={path}>Open full version!</a>
+                }
+            </form>
+            <hr/>
+
+            #if let Some(nav) = &nav {
+                #render_page_nav(path.clone(), nav.clone());
+                <hr/>
+            }
+
+            {Trust(content.clone())}
+
+            #if let Some(nav) = &nav {
+                <hr/>
+                #render_page_nav(path.clone(), nav.clone());
+            }
+
+            </body>
+        </html>
+    };
+
+    apply_profile(&template.render(&())?, profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::clients::{ClientFactory, SessionConfig};
+    use crate::server::fetch::FetchConfig;
+    use crate::server::profile::default_profile;
+    use crate::server::simplifier::cache::{PageCache, PageCacheConfig};
+    use crate::server::simplifier::process_page;
+    use crate::server::urlguard::{UrlGuard, UrlGuardConfig};
+    use axum::http::{StatusCode, header};
+    use axum::routing::get;
+    use std::io::Write;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test() -> anyhow::Result<()> {
+        let page = process_page(
+            "https://amigaforever.com/".to_string(),
+            "http://boingsearch.com/browse/".to_string(),
+            default_profile(),
+            &PageCache::new(PageCacheConfig::default()),
+            &ClientFactory::new(
+                Arc::new(UrlGuard::new(&UrlGuardConfig::default())),
+                FetchConfig::default(),
+                SessionConfig::default(),
+            )?
+            .shared(),
+            false,
+        )
+        .await?;
+
+        let mut file = std::fs::File::create("test.html")?;
+        write!(file, "{page}")?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_error_pages_are_not_cached() -> anyhow::Result<()> {
+        static HITS: AtomicUsize = AtomicUsize::new(0);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let page = |status| {
+            move || async move {
+                let hit = HITS.fetch_add(1, Ordering::SeqCst);
+                (
+                    status,
+                    [(header::CACHE_CONTROL, "max-age=600")],
+                    format!("<html><body><p>Visit number {hit}</p></body></html>"),
+                )
+            }
+        };
+        let router = axum::Router::new()
+            .route("/ok", get(page(StatusCode::OK)))
+            .route("/down", get(page(StatusCode::SERVICE_UNAVAILABLE)));
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let cache = PageCache::new(PageCacheConfig::default());
+        let client = ClientFactory::new(
+            Arc::new(UrlGuard::new(&UrlGuardConfig {
+                allow: vec!["127.0.0.1/32".to_string()],
+                deny: vec![],
+            })),
+            FetchConfig::default(),
+            SessionConfig::default(),
+        )?
+        .shared();
+        let browse = |path: &str| {
+            process_page(
+                format!("http://{addr}{path}"),
+                "/browse/".to_string(),
+                default_profile(),
+                &cache,
+                &client,
+                false,
+            )
+        };
+
+        let first = browse("/ok").await?;
+        assert_eq!(browse("/ok").await?, first);
+
+        let first = browse("/down").await?;
+        assert!(first.contains("Visit number"));
+        assert_ne!(browse("/down").await?, first);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_nav_links() {
+        let nav = super::PageNav {
+            current: 2,
+            total: 3,
+            showing_all: false,
+            browse_path: "http://boingsearch.com/browse/".to_string(),
+            params: vec![("profile".to_string(), "mosaic".to_string())],
+        };
+
+        assert_eq!(
+            nav.link("http://aminet.net/?a=1", "page=3"),
+            "http://boingsearch.com/browse/?url=http%3A%2F%2Faminet.net%2F%3Fa%3D1&profile=mosaic&page=3"
+        );
+    }
+
+    #[test]
+    fn test_page_chrome_follows_profile() -> anyhow::Result<()> {
+        let nav = super::PageNav {
+            current: 1,
+            total: 2,
+            showing_all: false,
+            browse_path: "/browse/".to_string(),
+            params: vec![],
+        };
+        let page = |name| {
+            super::proxy_page(
+                "http://aminet.net/".to_string(),
+                "<p>Aminet</p>".to_string(),
+                Some(nav.clone()),
+                crate::server::profile::find_profile(name).unwrap(),
+                &[],
+            )
+        };
+
+        assert!(page("default")?.contains("<center>"));
+
+        let mosaic = page("mosaic")?;
+        assert!(!mosaic.contains("<center>"));
+        assert!(!mosaic.contains("<br/>") && !mosaic.contains("<hr/>"));
+        assert!(mosaic.contains("Page 1 of 2"));
+        assert!(mosaic.contains("<input"));
+
+        Ok(())
+    }
+}
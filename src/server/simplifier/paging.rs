@@ -0,0 +1,92 @@
+use kuchiki::NodeRef;
+use kuchiki::parse_html;
+use kuchiki::traits::*;
+
+/// Elements that page can be split before, inline content always stays with its block
+const BLOCK_TAGS: &[&str] = &[
+    "p",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "ol",
+    "ul",
+    "dl",
+    "blockquote",
+    "pre",
+    "table",
+    "hr",
+    "center",
+    "form",
+];
+
+fn is_block(node: &NodeRef) -> bool {
+    node.as_element()
+        .is_some_and(|e| BLOCK_TAGS.contains(&e.name.local.as_ref()))
+}
+
+fn serialize_node(node: &NodeRef) -> anyhow::Result<String> {
+    let mut bytes = vec![];
+    node.serialize(&mut bytes)
+        .map_err(|e| anyhow::anyhow!(format!("Cannot serialize content: {e}")))?;
+
+    Ok(String::from_utf8(bytes)?)
+}
+
+/// Splits simplified page into pages of about `page_size` bytes at top level block boundaries.
+/// Single block that is bigger than page size gets page of its own
+pub fn split_pages(html: &str, page_size: usize) -> anyhow::Result<Vec<String>> {
+    if page_size == 0 || html.len() <= page_size {
+        return Ok(vec![html.to_string()]);
+    }
+
+    let document = parse_html().one(html);
+    let body = document
+        .select_first("body")
+        .map_err(|_| anyhow::anyhow!("Document has no body"))?;
+
+    let mut pages = vec![];
+    let mut current = String::new();
+
+    for child in body.as_node().children() {
+        let chunk = serialize_node(&child)?;
+
+        if is_block(&child) && !current.is_empty() && current.len() + chunk.len() > page_size {
+            pages.push(std::mem::take(&mut current));
+        }
+
+        current.push_str(&chunk);
+    }
+
+    if !current.is_empty() || pages.is_empty() {
+        pages.push(current);
+    }
+
+    Ok(pages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_at_blocks() {
+        let html = "<h1>Title</h1><p>First paragraph</p>Some <b>inline</b> text<p>Second one</p>";
+
+        let pages = split_pages(html, 30).unwrap();
+        assert_eq!(
+            pages,
+            vec![
+                "<h1>Title</h1>",
+                "<p>First paragraph</p>Some <b>inline</b> text",
+                "<p>Second one</p>",
+            ]
+        );
+        assert_eq!(pages.concat(), html);
+
+        assert_eq!(split_pages(html, 0).unwrap(), vec![html]);
+        assert_eq!(split_pages(html, 1024).unwrap(), vec![html]);
+    }
+}
@@ -0,0 +1,578 @@
+use std::collections::HashMap;
+
+use kuchiki::parse_html;
+use kuchiki::traits::*;
+use kuchiki::{Attribute, ExpandedName, NodeRef};
+use url::Url;
+
+use crate::server::image::fit_size;
+use crate::server::image::ilbm::IlbmMode;
+use crate::server::profile::{ImageFormat, ImageProfile, OutputProfile, TableMode};
+use crate::server::simplifier::forms::{FORM_TAGS, rewrite_forms};
+
+/// Tags that can't be put inside of inline ones
+const BLOCK_TAGS: &[&str] = &[
+    "p",
+    "div",
+    "ul",
+    "ol",
+    "dl",
+    "pre",
+    "blockquote",
+    "table",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+];
+
+/// Tree transform pass over simplified page: tag mapping and link rewriting
+pub struct PageTransform {
+    /// Url of the page after redirects, links are resolved against it
+    pub page_url: Url,
+    /// Simplifier endpoint that pages are routed through
+    pub base_path: String,
+    /// Tags that old browsers don't know and their replacements
+    pub tag_mapping: HashMap<String, String>,
+    pub tables: TableMode,
+    /// Profile that images are converted for
+    pub profile: &'static str,
+    /// Format and size that images are converted to
+    pub images: ImageProfile,
+    pub save_iff: bool,
+}
+
+impl PageTransform {
+    pub fn new(page_url: Url, base_path: String, profile: &OutputProfile) -> Self {
+        let tag_mapping = profile
+            .tag_mapping
+            .iter()
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect();
+
+        Self {
+            page_url,
+            base_path,
+            tag_mapping,
+            tables: profile.tables,
+            profile: profile.name,
+            images: profile.images.clone(),
+            save_iff: profile.images.enabled && profile.images.save_iff,
+        }
+    }
+
+    pub fn apply(&self, html: &str) -> anyhow::Result<String> {
+        let document = parse_html().one(html);
+
+        self.map_tags(&document);
+        match self.tables {
+            TableMode::Html => Self::add_table_borders(&document)?,
+            TableMode::Flatten => Self::flatten_tables(&document)?,
+        }
+        self.set_image_sizes(&document)?;
+        self.rewrite_links(&document)?;
+        rewrite_forms(&document, &self.page_url)?;
+
+        serialize_body(&document)
+    }
+
+    /// Replaces element with new one with other name, keeping attributes and children
+    fn rename_element(node: &NodeRef, new_name: &str) {
+        let Some(element) = node.as_element() else {
+            return;
+        };
+
+        let mut name = element.name.clone();
+        name.local = new_name.into();
+
+        let attributes = element.attributes.borrow().map.clone();
+        let renamed = NodeRef::new_element(name, attributes);
+
+        for child in node.children() {
+            renamed.append(child);
+        }
+
+        node.insert_before(renamed);
+        node.detach();
+    }
+
+    /// New element with same namespace as `like`
+    fn new_element(like: &NodeRef, name: &str) -> Option<NodeRef> {
+        let mut qual_name = like.as_element()?.name.clone();
+        qual_name.local = name.into();
+
+        Some(NodeRef::new_element(qual_name, Vec::new()))
+    }
+
+    /// Tables without borders look like mess of words in old browsers.
+    /// HTML 3.2 hasn't got row groups that parser adds, so they are unwrapped
+    fn add_table_borders(document: &NodeRef) -> anyhow::Result<()> {
+        let groups = document
+            .select("thead, tbody, tfoot")
+            .map_err(|_| anyhow::anyhow!("Cannot select tables"))?
+            .collect::<Vec<_>>();
+
+        for group in groups {
+            let group = group.as_node();
+            group.children().for_each(|c| group.insert_before(c));
+            group.detach();
+        }
+
+        for table in document
+            .select("table")
+            .map_err(|_| anyhow::anyhow!("Cannot select tables"))?
+        {
+            let mut attributes = table.attributes.borrow_mut();
+            attributes.insert("border", "1".to_string());
+            attributes.insert("cellpadding", "2".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Replaces tables with text lines: caption in bold, then row by row with cells separated
+    /// by `|`. Nested tables are flattened first, so they end up inside cells of outer ones.
+    /// Lines go in place of table without wrapper, cells often have paragraphs and lists that
+    /// can't be put into `<p>`
+    fn flatten_tables(document: &NodeRef) -> anyhow::Result<()> {
+        let tables = document
+            .select("table")
+            .map_err(|_| anyhow::anyhow!("Cannot select tables"))?
+            .collect::<Vec<_>>();
+
+        for table in tables.iter().rev() {
+            let table = table.as_node();
+
+            let rows = table
+                .descendants()
+                .filter(|n| {
+                    n.as_element()
+                        .is_some_and(|e| matches!(e.name.local.as_ref(), "caption" | "tr"))
+                })
+                .collect::<Vec<_>>();
+
+            for row in rows {
+                let is_caption = row
+                    .as_element()
+                    .is_some_and(|e| e.name.local.as_ref() == "caption");
+
+                if is_caption {
+                    if let Some(bold) = Self::new_element(table, "b") {
+                        row.children().for_each(|c| bold.append(c));
+                        table.insert_before(bold);
+                    }
+                } else {
+                    let cells = row
+                        .children()
+                        .filter(|n| {
+                            n.as_element()
+                                .is_some_and(|e| matches!(e.name.local.as_ref(), "td" | "th"))
+                        })
+                        .collect::<Vec<_>>();
+
+                    for (i, cell) in cells.iter().enumerate() {
+                        if i > 0 {
+                            table.insert_before(NodeRef::new_text(" | "));
+                        }
+
+                        // Bold can't hold blocks, such header cells are left as they are
+                        let is_header = cell
+                            .as_element()
+                            .is_some_and(|e| e.name.local.as_ref() == "th")
+                            && !cell.children().any(|c| Self::is_block(&c));
+                        let target = match is_header {
+                            true => Self::new_element(table, "b")
+                                .inspect(|b| table.insert_before(b.clone())),
+                            false => None,
+                        };
+
+                        for child in cell.children() {
+                            match &target {
+                                Some(target) => target.append(child),
+                                None => table.insert_before(child),
+                            }
+                        }
+                    }
+                }
+
+                if let Some(br) = Self::new_element(table, "br") {
+                    table.insert_before(br);
+                }
+            }
+
+            table.detach();
+        }
+
+        Ok(())
+    }
+
+    fn is_block(node: &NodeRef) -> bool {
+        node.as_element()
+            .is_some_and(|e| BLOCK_TAGS.contains(&e.name.local.as_ref()))
+    }
+
+    fn map_tags(&self, document: &NodeRef) {
+        // Collecting first - renaming changes tree while we are walking it
+        let to_rename = document
+            .descendants()
+            .filter_map(|node| {
+                let new_name = self
+                    .tag_mapping
+                    .get(node.as_element()?.name.local.as_ref())?;
+
+                Some((node, new_name.clone()))
+            })
+            .collect::<Vec<_>>();
+
+        for (node, new_name) in to_rename {
+            Self::rename_element(&node, &new_name);
+        }
+    }
+
+    /// Layout is a part of url, so browser doesn't show cached image after screen mode change
+    fn converted_image(&self, format: ImageFormat, absolute: &Url) -> String {
+        format!(
+            "/convert?format={}&profile={}&width={}&height={}&aspect={}&url={}",
+            format.name(),
+            self.profile,
+            self.images.max_width,
+            self.images.max_height,
+            urlencoding::encode(&self.images.aspect.to_string()),
+            urlencoding::encode(absolute.as_str())
+        )
+    }
+
+    /// Where resolved link should point to, only images go to converter. Frames and other
+    /// embedded things are opened with simplifier
+    fn proxied_target(&self, is_image: bool, absolute: &Url) -> String {
+        let encoded = urlencoding::encode(absolute.as_str());
+
+        match is_image {
+            true => self.converted_image(self.images.format, absolute),
+            false => format!("{}?url={encoded}", self.base_path),
+        }
+    }
+
+    fn is_image(element: &kuchiki::ElementData) -> bool {
+        match element.name.local.as_ref() {
+            "img" => true,
+            "input" => element
+                .attributes
+                .borrow()
+                .get("type")
+                .is_some_and(|t| t.trim().eq_ignore_ascii_case("image")),
+            _ => false,
+        }
+    }
+
+    /// Sizes that converted images will have, so page is laid out before images arrive. They
+    /// are known only when source has both numeric `width` and `height`. Otherwise sizes are
+    /// dropped, browser would stretch converted image to them, and takes size from image itself
+    fn set_image_sizes(&self, document: &NodeRef) -> anyhow::Result<()> {
+        for image in document
+            .select("img")
+            .map_err(|_| anyhow::anyhow!("Cannot select images"))?
+        {
+            let mut attributes = image.attributes.borrow_mut();
+            let mut size = |name: &str| {
+                attributes
+                    .remove(name)
+                    .and_then(|attr| attr.value.trim().parse::<u32>().ok())
+                    .filter(|value| *value > 0)
+            };
+
+            let Some((width, height)) = size("width").zip(size("height")) else {
+                continue;
+            };
+
+            let (width, height) = fit_size(
+                width,
+                height,
+                self.images.max_width,
+                self.images.max_height,
+                self.images.aspect,
+            );
+            attributes.insert("width", width.to_string());
+            attributes.insert("height", height.to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Puts "save as IFF" link after image, or after link that image is in
+    fn add_iff_link(&self, image: &NodeRef, absolute: &Url) {
+        let Some(element) = image.as_element() else {
+            return;
+        };
+
+        let anchor = image
+            .ancestors()
+            .find(|node| node.as_element().is_some_and(|e| &e.name.local == "a"))
+            .unwrap_or(image.clone());
+
+        let mut name = element.name.clone();
+        name.local = "a".into();
+        let link = NodeRef::new_element(
+            name,
+            [(
+                ExpandedName::new("", "href"),
+                Attribute {
+                    prefix: None,
+                    value: self.converted_image(ImageFormat::Ilbm(IlbmMode::Indexed), absolute),
+                },
+            )],
+        );
+        link.append(NodeRef::new_text("[save as IFF]"));
+
+        anchor.insert_after(link);
+        anchor.insert_after(NodeRef::new_text(" "));
+    }
+
+    /// Resolves every `href` and `src` against page url and routes them through simplifier
+    /// or image converter
+    fn rewrite_links(&self, document: &NodeRef) -> anyhow::Result<()> {
+        let mut images = vec![];
+
+        for element in document
+            .select("[href], [src]")
+            .map_err(|_| anyhow::anyhow!("Cannot select links"))?
+        {
+            let is_image = Self::is_image(&element);
+            let mut attributes = element.attributes.borrow_mut();
+
+            for attr in ["href", "src"] {
+                let Some(value) = attributes.get(attr).map(str::trim) else {
+                    continue;
+                };
+
+                // In-page anchors work without any proxying
+                if value.is_empty() || value.starts_with('#') {
+                    continue;
+                }
+
+                let Ok(absolute) = self.page_url.join(value) else {
+                    continue;
+                };
+
+                if absolute.scheme() != "http" && absolute.scheme() != "https" {
+                    continue;
+                }
+
+                if attr == "src" && &element.name.local == "img" {
+                    images.push((element.as_node().clone(), absolute.clone()));
+                }
+
+                let target = self.proxied_target(attr == "src" && is_image, &absolute);
+                attributes.insert(attr, target);
+            }
+        }
+
+        // Added after walking the tree, so new links aren't rewritten again
+        if self.save_iff {
+            for (image, absolute) in images {
+                self.add_iff_link(&image, &absolute);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Skeleton of our own pages, kept whatever profile allows
+const PAGE_TAGS: &[&str] = &["html", "head", "title", "body"];
+
+/// Our own pages get the same tag mapping and whitelist as simplified ones,
+/// tags that profile doesn't allow are replaced with their content
+pub fn apply_profile(page: &str, profile: &OutputProfile) -> anyhow::Result<String> {
+    let document = parse_html().one(page);
+
+    // Collecting first - renaming and unwrapping change tree while we are walking it
+    let elements = document
+        .descendants()
+        .filter(|node| node.as_element().is_some())
+        .collect::<Vec<_>>();
+
+    for node in elements {
+        let Some(name) = node.as_element().map(|e| e.name.local.to_string()) else {
+            continue;
+        };
+        let name = name.as_str();
+
+        if let Some(new_name) = profile.tag_mapping.get(name) {
+            PageTransform::rename_element(&node, new_name);
+        } else if !profile.tags.contains(name)
+            && !PAGE_TAGS.contains(&name)
+            && !FORM_TAGS.contains(&name)
+        {
+            node.children().for_each(|c| node.insert_before(c));
+            node.detach();
+        }
+    }
+
+    let mut bytes = vec![];
+    document
+        .serialize(&mut bytes)
+        .map_err(|e| anyhow::anyhow!(format!("Cannot serialize page: {e}")))?;
+
+    Ok(String::from_utf8(bytes)?)
+}
+
+/// Serializes contents of the body - parser wraps fragment into html/head/body
+pub fn serialize_body(document: &NodeRef) -> anyhow::Result<String> {
+    let body = document
+        .select_first("body")
+        .map_err(|_| anyhow::anyhow!("Document has no body"))?;
+
+    let mut bytes = vec![];
+    for child in body.as_node().children() {
+        child
+            .serialize(&mut bytes)
+            .map_err(|e| anyhow::anyhow!(format!("Cannot serialize content: {e}")))?;
+    }
+
+    Ok(String::from_utf8(bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transform(page_url: &str) -> PageTransform {
+        PageTransform::new(
+            Url::parse(page_url).unwrap(),
+            "http://boing/browse/".to_string(),
+            crate::server::profile::default_profile(),
+        )
+    }
+
+    #[test]
+    fn test_rewrite_relative_links() -> anyhow::Result<()> {
+        let html = r##"<p>
+            <a href="intro.html">Relative</a>
+            <a href="/about?a=1&amp;b=2">Root</a>
+            <a href="../api/">Parent</a>
+            <a href='//cdn.example.org/file'>Protocol</a>
+            <a href="http://other.org/">Absolute</a>
+            <a href="#top">Anchor</a>
+            <a href="mailto:boing@example.com">Mail</a>
+            <img src="img/ball.gif" alt="Ball">
+            <input type="image" src="go.gif">
+            <iframe src="frame.html"></iframe>
+        </p>"##;
+
+        let result = transform("https://example.com/docs/guide/index.html?x=1").apply(html)?;
+
+        for expected in [
+            r#"href="http://boing/browse/?url=https%3A%2F%2Fexample.com%2Fdocs%2Fguide%2Fintro.html""#,
+            r#"href="http://boing/browse/?url=https%3A%2F%2Fexample.com%2Fabout%3Fa%3D1%26b%3D2""#,
+            r#"href="http://boing/browse/?url=https%3A%2F%2Fexample.com%2Fdocs%2Fapi%2F""#,
+            r#"href="http://boing/browse/?url=https%3A%2F%2Fcdn.example.org%2Ffile""#,
+            r#"href="http://boing/browse/?url=http%3A%2F%2Fother.org%2F""#,
+            r##"href="#top""##,
+            r#"href="mailto:boing@example.com""#,
+            r#"src="/convert?format=png&amp;profile=default&amp;width=320&amp;height=240&amp;aspect=1%3A1&amp;url=https%3A%2F%2Fexample.com%2Fdocs%2Fguide%2Fimg%2Fball.gif""#,
+            r#"src="/convert?format=png&amp;profile=default&amp;width=320&amp;height=240&amp;aspect=1%3A1&amp;url=https%3A%2F%2Fexample.com%2Fdocs%2Fguide%2Fgo.gif""#,
+            r#"src="http://boing/browse/?url=https%3A%2F%2Fexample.com%2Fdocs%2Fguide%2Fframe.html""#,
+        ] {
+            assert!(
+                result.contains(expected),
+                "{expected} not found in {result}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_save_as_iff_links() -> anyhow::Result<()> {
+        let html = r#"<p><a href="/boing.html"><img src="boing.gif"></a></p>"#;
+
+        let result = transform("https://example.com/").apply(html)?;
+        assert_eq!(
+            result,
+            concat!(
+                r#"<p><a href="http://boing/browse/?url=https%3A%2F%2Fexample.com%2Fboing.html">"#,
+                r#"<img src="/convert?format=png&amp;profile=default&amp;width=320&amp;height=240&amp;aspect=1%3A1&amp;url=https%3A%2F%2Fexample.com%2Fboing.gif"></a> "#,
+                r#"<a href="/convert?format=iff&amp;profile=default&amp;width=320&amp;height=240&amp;aspect=1%3A1&amp;url=https%3A%2F%2Fexample.com%2Fboing.gif">[save as IFF]</a></p>"#
+            )
+        );
+
+        let mosaic = PageTransform::new(
+            Url::parse("https://example.com/")?,
+            "http://boing/browse/".to_string(),
+            crate::server::profile::find_profile("mosaic").unwrap(),
+        );
+        assert!(!mosaic.apply(html)?.contains("IFF"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_image_sizes() -> anyhow::Result<()> {
+        let html = r#"<img src="a.gif" width="1024" height="768"><img src="b.gif" width="50%"><img src="c.gif" width=" 64 " height="32">"#;
+
+        let mut transform = transform("https://example.com/");
+        transform.save_iff = false;
+        transform.images.aspect = crate::server::image::PixelAspect { x: 1, y: 2 };
+        let result = transform.apply(html)?;
+
+        // Default profile fits images into 320x240, hires pixels are twice as tall
+        assert!(result.contains(r#"height="120" src="/convert?format=png&amp;profile=default&amp;width=320&amp;height=240&amp;aspect=1%3A2&amp;url=https%3A%2F%2Fexample.com%2Fa.gif" width="320""#));
+        assert!(result.contains(r#"<img src="/convert?format=png&amp;profile=default&amp;width=320&amp;height=240&amp;aspect=1%3A2&amp;url=https%3A%2F%2Fexample.com%2Fb.gif">"#));
+        assert!(result.contains(r#"height="16" src="/convert?format=png&amp;profile=default&amp;width=320&amp;height=240&amp;aspect=1%3A2&amp;url=https%3A%2F%2Fexample.com%2Fc.gif" width="64""#));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tag_mapping_keeps_text() -> anyhow::Result<()> {
+        let html =
+            r#"<p title="em>"><strong class="x">Use <em>strong></em> and em> in text</strong></p>"#;
+
+        let result = transform("https://example.com/").apply(html)?;
+
+        assert_eq!(
+            result,
+            r#"<p title="em>"><b class="x">Use <i>strong&gt;</i> and em&gt; in text</b></p>"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tables_per_profile() -> anyhow::Result<()> {
+        let html = "<table><caption>Models</caption><tr><th>Name</th><th>CPU</th></tr>\
+            <tr><td>A1200</td><td>68020</td></tr></table><pre>  move.l  d0,d1\n  rts</pre>";
+
+        let result = transform("https://example.com/").apply(html)?;
+        assert_eq!(
+            result,
+            "<table border=\"1\" cellpadding=\"2\"><caption>Models</caption><tr><th>Name</th>\
+            <th>CPU</th></tr><tr><td>A1200</td><td>68020</td></tr></table>\
+            <pre>  move.l  d0,d1\n  rts</pre>"
+        );
+
+        let lynx = PageTransform::new(
+            Url::parse("https://example.com/")?,
+            "http://boing/browse/".to_string(),
+            crate::server::profile::find_profile("lynx").unwrap(),
+        );
+        assert_eq!(
+            lynx.apply(html)?,
+            "<b>Models</b><br><b>Name</b> | <b>CPU</b><br>A1200 | 68020<br>\
+            <pre>  move.l  d0,d1\n  rts</pre>"
+        );
+
+        let blocks = "<table><tr><th><p>Model</p></th><td><p>A1200</p><ul><li>AGA</li></ul></td>\
+            </tr></table>";
+        assert_eq!(
+            lynx.apply(blocks)?,
+            "<p>Model</p> | <p>A1200</p><ul><li>AGA</li></ul><br>"
+        );
+
+        Ok(())
+    }
+}
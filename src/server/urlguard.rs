@@ -0,0 +1,242 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, LazyLock};
+
+use hyper::client::connect::dns::Name;
+use ipnet::IpNet;
+use log::warn;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use reqwest::redirect;
+use serde::{Deserialize, Serialize};
+use url::{Host, Url};
+
+/// How many redirects are followed, same as reqwest does by default
+const MAX_REDIRECTS: usize = 10;
+
+/// Loopback, private, link-local and other addresses that aren't in public internet
+static RESERVED_RANGES: LazyLock<Vec<IpNet>> = LazyLock::new(|| {
+    [
+        "0.0.0.0/8",
+        "10.0.0.0/8",
+        "100.64.0.0/10",
+        "127.0.0.0/8",
+        "169.254.0.0/16",
+        "172.16.0.0/12",
+        "192.0.0.0/24",
+        "192.0.2.0/24",
+        "192.88.99.0/24",
+        "192.168.0.0/16",
+        "198.18.0.0/15",
+        "198.51.100.0/24",
+        "203.0.113.0/24",
+        "224.0.0.0/3",
+        "::/128",
+        "::1/128",
+        "::/96",
+        "64:ff9b::/96",
+        "64:ff9b:1::/48",
+        "100::/64",
+        "2001:db8::/32",
+        "2002::/16",
+        "fc00::/7",
+        "fe80::/10",
+        "ff00::/8",
+    ]
+    .iter()
+    .filter_map(|net| net.parse().ok())
+    .collect()
+});
+
+/// `[url_guard]` section of config.toml
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UrlGuardConfig {
+    /// Hosts or CIDR ranges that are allowed even if they are reserved
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// Hosts or CIDR ranges that are blocked in addition to reserved ones
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct RuleList {
+    nets: Vec<IpNet>,
+    /// Matches host itself and all its subdomains
+    hosts: Vec<String>,
+}
+
+impl RuleList {
+    fn new(rules: &[String]) -> Self {
+        let mut list = Self::default();
+
+        for rule in rules.iter().map(|r| r.trim()) {
+            if let Ok(net) = rule.parse::<IpNet>() {
+                list.nets.push(net);
+            } else if let Ok(ip) = rule.parse::<IpAddr>() {
+                list.nets.push(IpNet::from(ip));
+            } else if !rule.is_empty() {
+                list.hosts
+                    .push(rule.trim_start_matches('.').to_ascii_lowercase());
+            }
+        }
+
+        list
+    }
+
+    fn contains_ip(&self, ip: IpAddr) -> bool {
+        self.nets.iter().any(|net| net.contains(&ip))
+    }
+
+    fn contains_host(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+
+        self.hosts
+            .iter()
+            .any(|h| host == *h || host.ends_with(&format!(".{h}")))
+    }
+}
+
+/// Keeps visitors from reaching server's own network through simplifier and image converter
+#[derive(Debug)]
+pub struct UrlGuard {
+    allow: RuleList,
+    deny: RuleList,
+}
+
+impl UrlGuard {
+    pub fn new(config: &UrlGuardConfig) -> Self {
+        Self {
+            allow: RuleList::new(&config.allow),
+            deny: RuleList::new(&config.deny),
+        }
+    }
+
+    pub fn is_ip_allowed(&self, ip: IpAddr) -> bool {
+        // IPv4 mapped addresses must follow IPv4 rules
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            v4 => v4,
+        };
+
+        if self.allow.contains_ip(ip) {
+            return true;
+        }
+
+        !self.deny.contains_ip(ip) && !RESERVED_RANGES.iter().any(|net| net.contains(&ip))
+    }
+
+    /// Scheme and host checks, addresses of named hosts are checked by resolver
+    pub fn check_url(&self, url: &Url) -> anyhow::Result<()> {
+        if url.scheme() != "http" && url.scheme() != "https" {
+            anyhow::bail!("Only http and https links can be opened");
+        }
+
+        let allowed = match url.host() {
+            Some(Host::Ipv4(ip)) => self.is_ip_allowed(IpAddr::V4(ip)),
+            Some(Host::Ipv6(ip)) => self.is_ip_allowed(IpAddr::V6(ip)),
+            Some(Host::Domain(host)) => {
+                self.allow.contains_host(host) || !self.deny.contains_host(host)
+            }
+            None => false,
+        };
+
+        if !allowed {
+            warn!("Blocked request to {url}");
+            anyhow::bail!("This address can't be opened");
+        }
+
+        Ok(())
+    }
+
+    /// Client that can't connect to blocked addresses, also after redirects
+    pub fn client_builder(self: &Arc<Self>) -> reqwest::ClientBuilder {
+        let guard = self.clone();
+        let policy = redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("Too many redirects")
+            } else if let Err(e) = guard.check_url(attempt.url()) {
+                attempt.error(e.to_string())
+            } else {
+                attempt.follow()
+            }
+        });
+
+        reqwest::Client::builder()
+            .user_agent(crate::USER_AGENT)
+            .redirect(policy)
+            .dns_resolver(Arc::new(GuardedResolver {
+                guard: self.clone(),
+            }))
+    }
+}
+
+/// System resolver that drops blocked addresses from the answer
+struct GuardedResolver {
+    guard: Arc<UrlGuard>,
+}
+
+impl Resolve for GuardedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let guard = self.guard.clone();
+
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs = tokio::net::lookup_host((host, 0))
+                .await?
+                .collect::<Vec<SocketAddr>>();
+
+            let allowed = if guard.allow.contains_host(host) {
+                addrs
+            } else {
+                addrs
+                    .into_iter()
+                    .filter(|addr| guard.is_ip_allowed(addr.ip()))
+                    .collect()
+            };
+
+            if allowed.is_empty() {
+                warn!("Blocked resolving of {host}");
+                return Err(format!("Address of {host} can't be opened").into());
+            }
+
+            Ok(Box::new(allowed.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserved_and_configured_ranges() {
+        let guard = UrlGuard::new(&UrlGuardConfig {
+            allow: vec!["192.168.1.10".to_string()],
+            deny: vec!["8.8.8.0/24".to_string(), "evil.example".to_string()],
+        });
+        let check = |url: &str| guard.check_url(&Url::parse(url).unwrap()).is_ok();
+
+        assert!(check("http://aminet.net/"));
+        assert!(check("https://1.1.1.1/"));
+        assert!(!check("http://127.0.0.1:8808/"));
+        assert!(!check("http://169.254.169.254/latest/meta-data/"));
+        assert!(!check("http://192.168.1.147:8808/"));
+        assert!(!check("http://[::1]/"));
+        assert!(!check("http://[::ffff:10.0.0.1]/"));
+        assert!(!check("http://[64:ff9b::7f00:1]/"));
+        assert!(!check("http://[2002:7f00:1::]/"));
+        assert!(!check("http://[::7f00:1]/"));
+        assert!(!check("ftp://aminet.net/"));
+        assert!(!check("file:///etc/passwd"));
+        assert!(!check("http://8.8.8.8/"));
+        assert!(!check("http://www.evil.example/"));
+        assert!(check("http://192.168.1.10/"));
+    }
+
+    #[tokio::test]
+    async fn test_resolver_blocks_private_addresses() {
+        let guard = Arc::new(UrlGuard::new(&UrlGuardConfig::default()));
+        let resolver = GuardedResolver { guard };
+        let resolved = resolver.resolve("localhost".parse().unwrap()).await;
+        assert!(resolved.is_err());
+    }
+}